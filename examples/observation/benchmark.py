@@ -0,0 +1,40 @@
+import statistics
+import sys
+
+from pokey_engine import MctsConfig
+from utilities import Utilities
+
+# Measures MCTS throughput on the example teams, once per parallel mode and once for
+# the single-threaded search. Run it on two builds to compare their it/s.
+# Usage: python benchmark.py [time_limit_ms] [runs]
+if __name__ == "__main__":
+    time_limit = int(sys.argv[1]) if len(sys.argv) > 1 else 1000
+    runs = int(sys.argv[2]) if len(sys.argv) > 2 else 5
+
+    state = Utilities().initialize_state("./team1.txt", "./team2.txt")
+
+    # warm up the thread pool
+    state.perform_mcts_search(time_limit=100)
+
+    for mode in ["root", "tree"]:
+        config = MctsConfig(parallel_mode=mode)
+        rates = []
+        for _ in range(runs):
+            result = state.perform_mcts_search(time_limit=time_limit, config=config)
+            diagnostics = result.diagnostics
+            rates.append(diagnostics.iterations_per_second)
+            print(
+                f"{mode}: {diagnostics.iterations_per_second:.0f} it/s, "
+                f"{diagnostics.node_count} nodes, max depth {diagnostics.max_depth}, "
+                f"best {result.best_move}"
+            )
+
+        print(f"{mode} median: {statistics.median(rates):.0f} it/s over {runs} runs")
+
+    rates = []
+    for _ in range(runs):
+        result = state.perform_mcts_search_st(time_limit=time_limit)
+        rates.append(result.diagnostics.iterations_per_second)
+        print(f"single: {result.diagnostics.iterations_per_second:.0f} it/s")
+
+    print(f"single median: {statistics.median(rates):.0f} it/s over {runs} runs")
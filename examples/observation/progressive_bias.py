@@ -0,0 +1,33 @@
+import statistics
+import sys
+
+from pokey_engine import MctsConfig
+from utilities import Utilities
+
+# Compares short searches with and without the damage prior's progressive bias.
+# Each setting searches the opening position `runs` times and is checked against a
+# long reference search.
+# Usage: python progressive_bias.py [time_limit_ms] [runs] [bias]
+if __name__ == "__main__":
+    time_limit = int(sys.argv[1]) if len(sys.argv) > 1 else 100
+    runs = int(sys.argv[2]) if len(sys.argv) > 2 else 10
+    bias = float(sys.argv[3]) if len(sys.argv) > 3 else 1.0
+
+    state = Utilities().initialize_state("./team1.txt", "./team2.txt")
+
+    reference = state.perform_mcts_search_st(time_limit=time_limit * 50)
+    print(f"reference: best {reference.best_move}, score {reference.score:.3f}")
+
+    for progressive_bias in [0.0, bias]:
+        config = MctsConfig(progressive_bias=progressive_bias)
+        agreements = 0
+        rates = []
+        for _ in range(runs):
+            result = state.perform_mcts_search_st(time_limit=time_limit, config=config)
+            agreements += result.best_move == reference.best_move
+            rates.append(result.diagnostics.iterations_per_second)
+
+        print(
+            f"bias {progressive_bias}: agreed with the reference {agreements}/{runs} "
+            f"times, median {statistics.median(rates):.0f} it/s"
+        )
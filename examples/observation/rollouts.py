@@ -0,0 +1,57 @@
+import random
+import statistics
+import sys
+
+from pokey_engine import MctsConfig
+from utilities import Utilities
+
+# Compares rollout leaf evaluation against the static evaluation late in a battle.
+# Both sides self-play with short static searches until `turns` turns have passed, then
+# every policy searches that position and is checked against a long reference search.
+# Usage: python rollouts.py [turns] [time_limit_ms] [runs]
+POLICIES = ["none", "random", "max_damage", "heuristic"]
+
+if __name__ == "__main__":
+    turns = int(sys.argv[1]) if len(sys.argv) > 1 else 20
+    time_limit = int(sys.argv[2]) if len(sys.argv) > 2 else 1000
+    runs = int(sys.argv[3]) if len(sys.argv) > 3 else 5
+
+    state = Utilities().initialize_state("./team1.txt", "./team2.txt")
+    rng = random.Random(0)
+
+    for _ in range(turns):
+        if state.battle_is_over() != 0:
+            break
+        side_one = state.perform_mcts_search_st(time_limit=100, seed=0)
+        side_two = state.perform_mcts_search_st(
+            time_limit=100, seed=0, perspective="side_two"
+        )
+        branches = state.generate_instructions(side_one.best_move, side_two.best_move)
+        weights = [branch.percentage for branch in branches]
+        state.apply_instructions(rng.choices(range(len(branches)), weights)[0])
+
+    if state.battle_is_over() != 0:
+        sys.exit("The battle ended before the requested turn, try fewer turns")
+
+    reference = state.perform_mcts_search_st(
+        time_limit=time_limit * 10,
+        config=MctsConfig(rollout_policy="random", rollout_depth=100),
+    )
+    print(f"reference: best {reference.best_move}, score {reference.score:.3f}")
+
+    for policy in POLICIES:
+        config = MctsConfig(
+            rollout_policy=policy, rollout_weights=(1.0, 0.002, -0.1)
+        )
+        agreements = 0
+        rates = []
+        for _ in range(runs):
+            result = state.perform_mcts_search_st(time_limit=time_limit, config=config)
+            agreements += result.best_move == reference.best_move
+            rates.append(result.diagnostics.iterations_per_second)
+            print(f"{policy}: best {result.best_move}, score {result.score:.3f}")
+
+        print(
+            f"{policy}: agreed with the reference {agreements}/{runs} times, "
+            f"median {statistics.median(rates):.0f} it/s"
+        )
@@ -1,17 +1,36 @@
 use pokezoo::observations;
+use pyconfig::PyMctsConfig;
+use pyhandle::PySearchHandle;
 use pymove::PyMove;
 use pyo3::prelude::*;
 use pypokemon::PyPokemon;
+use pypuct::PyPuctSearch;
+use pysearch::{PyMoveStats, PySearchDiagnostics, PySearchResult};
+use pysession::PySearchSession;
 use pyside::{PySide, PySideConditions};
 use pystate::PyState;
 
+mod mcts_config;
+mod mcts_duct;
+mod mcts_export;
 mod mcts_ol;
 mod mcts_ol_st;
+mod mcts_result;
+mod observation;
 mod pokezoo;
+mod pyconfig;
+mod pyhandle;
 mod pymove;
 mod pypokemon;
+mod pypuct;
+mod pysearch;
+mod pysession;
 mod pyside;
 mod pystate;
+mod rollout;
+mod solver;
+mod state_hash;
+mod valid_moves;
 
 #[allow(clippy::wildcard_imports)]
 #[pymodule]
@@ -25,6 +44,13 @@ mod pokey_engine {
         m.add_class::<PyMove>()?;
         m.add_class::<PyPokemon>()?;
         m.add_class::<PySideConditions>()?;
+        m.add_class::<PyMctsConfig>()?;
+        m.add_class::<PySearchSession>()?;
+        m.add_class::<PySearchHandle>()?;
+        m.add_class::<PyPuctSearch>()?;
+        m.add_class::<PySearchResult>()?;
+        m.add_class::<PyMoveStats>()?;
+        m.add_class::<PySearchDiagnostics>()?;
 
         Ok(())
     }
@@ -0,0 +1,252 @@
+use poke_engine::state::State;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::str::FromStr;
+
+// How each side picks its move at a node of the simultaneous-move search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimultaneousPolicy {
+    // decoupled UCB1, the final strategy is the visit distribution
+    Ucb,
+    Exp3,
+    RegretMatching,
+}
+
+impl FromStr for SimultaneousPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ucb" | "duct" => Ok(Self::Ucb),
+            "exp3" => Ok(Self::Exp3),
+            "regret_matching" | "rm" => Ok(Self::RegretMatching),
+            _ => Err(()),
+        }
+    }
+}
+
+// How the parallel searcher spreads its threads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParallelMode {
+    // one independent tree per thread, root statistics are summed at the end
+    Root,
+    // every thread searches one shared tree, using virtual loss to spread out
+    Tree,
+}
+
+impl FromStr for ParallelMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "root" => Ok(Self::Root),
+            "tree" => Ok(Self::Tree),
+            _ => Err(()),
+        }
+    }
+}
+
+// How a leaf is played out before it's scored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RolloutPolicy {
+    // score the leaf itself with the static evaluation
+    None,
+    Random,
+    // each side plays the move with the most expected damage
+    MaxDamage,
+    // each side plays the move with the best score under the rollout weights
+    Heuristic,
+}
+
+impl FromStr for RolloutPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" | "static" => Ok(Self::None),
+            "random" => Ok(Self::Random),
+            "max_damage" => Ok(Self::MaxDamage),
+            "heuristic" => Ok(Self::Heuristic),
+            _ => Err(()),
+        }
+    }
+}
+
+// Weights of the move features the heuristic rollout policy adds up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RolloutWeights {
+    // expected damage to the opposing active, as a share of its max HP
+    pub damage: f32,
+    // expected change in evaluation points for the side moving
+    pub evaluation: f32,
+    // 1 for a switch, 0 otherwise
+    pub switch: f32,
+}
+
+impl Default for RolloutWeights {
+    fn default() -> Self {
+        Self {
+            damage: 1.0,
+            evaluation: 0.0,
+            switch: 0.0,
+        }
+    }
+}
+
+// How the move to play is picked from the root statistics once a search ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FinalMovePolicy {
+    MaxVisits,
+    // highest mean value, among moves with at least one visit
+    MaxValue,
+    // best worst ratio of visits and value to the best of each, so a move that leads
+    // both wins outright
+    RobustMax,
+    // sampled in proportion to visits^(1 / temperature)
+    Temperature,
+}
+
+impl FromStr for FinalMovePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "max_visits" | "visits" => Ok(Self::MaxVisits),
+            "max_value" | "value" => Ok(Self::MaxValue),
+            "robust_max" | "robust" => Ok(Self::RobustMax),
+            "temperature" | "sample" => Ok(Self::Temperature),
+            _ => Err(()),
+        }
+    }
+}
+
+// Which side a search plays as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Perspective {
+    SideOne,
+    SideTwo,
+}
+
+impl FromStr for Perspective {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "side_one" | "s1" | "p1" => Ok(Self::SideOne),
+            "side_two" | "s2" | "p2" => Ok(Self::SideTwo),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Perspective {
+    // The searchers always play side one, so side two searches a mirrored copy
+    pub fn orient(self, state: &State) -> State {
+        let mut oriented = state.clone();
+        if self == Perspective::SideTwo {
+            std::mem::swap(&mut oriented.side_one, &mut oriented.side_two);
+        }
+        oriented
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MctsConfig {
+    // c in sqrt(c * ln(N) / n) for our moves
+    pub exploration_constant: f32,
+    // same constant, used when picking the opponent's move
+    pub opponent_exploration_constant: f32,
+    // scale applied to the evaluation difference before the sigmoid
+    pub sigmoid_scale: f32,
+    // iterations run between stop checks in the parallel searcher, and leaves
+    // gathered per network call in the PUCT search
+    pub batch_size: u32,
+    // hard cap on root visits, regardless of the time or iteration budget
+    pub max_visits: i64,
+    // selection used by the simultaneous-move search
+    pub simultaneous_policy: SimultaneousPolicy,
+    // share of uniform exploration mixed into the Exp3 and regret matching strategies
+    pub exploration_rate: f32,
+    // expand every instruction branch of a joint move as its own node in the
+    // simultaneous-move search instead of sampling one into a shared child
+    pub chance_nodes: bool,
+    // root or tree parallelism in the parallel searcher
+    pub parallel_mode: ParallelMode,
+    // c in Q + c * P * sqrt(N) / (1 + n) when selecting our moves with network priors
+    pub puct_constant: f32,
+    // how leaves are played out before the static evaluation scores them
+    pub rollout_policy: RolloutPolicy,
+    // turns a rollout plays at most, battles that end sooner get their exact result
+    pub rollout_depth: usize,
+    pub rollout_weights: RolloutWeights,
+    // how best_move is picked from the root statistics
+    pub final_move_policy: FinalMovePolicy,
+    // sharpens (below 1) or flattens (above 1) temperature sampling, 0 plays max visits
+    pub temperature: f32,
+    // concentration of the Dirichlet noise mixed into the root priors of the PUCT search,
+    // the only search with priors
+    pub dirichlet_alpha: f32,
+    // share of the root priors replaced by that noise, 0 turns it off. The other
+    // searches reject anything above 0.
+    pub dirichlet_epsilon: f32,
+    // share scores between identical states reached by different move orders in the
+    // single-threaded search. The other searches reject it, as they do the solver.
+    pub transpositions: bool,
+    // prove wins and losses from terminal states up in the single-threaded search
+    pub solver: bool,
+    // node and byte budgets of the parallel search, shared between its trees. The
+    // other searches reject them.
+    pub max_nodes: Option<usize>,
+    pub max_memory_bytes: Option<usize>,
+    // at the budget, drop the least visited subtrees instead of only no longer expanding.
+    // The tree-parallel mode always stops expanding, its threads hold node indices.
+    pub prune: bool,
+    // weight of the damage prior added to UCB1 at the root as w * prior / (1 + n),
+    // which also orders the root's untried moves. 0 turns it off.
+    pub progressive_bias: f32,
+    // seeds every random choice of a search, None draws a fresh seed each time
+    pub seed: Option<u64>,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            exploration_constant: 2.0,
+            opponent_exploration_constant: 2.0,
+            // Tuned so that ~200 points is very close to 1.0
+            sigmoid_scale: 0.0125,
+            batch_size: 20,
+            max_visits: 10_000_000,
+            simultaneous_policy: SimultaneousPolicy::RegretMatching,
+            exploration_rate: 0.1,
+            chance_nodes: false,
+            parallel_mode: ParallelMode::Root,
+            puct_constant: 1.5,
+            rollout_policy: RolloutPolicy::None,
+            rollout_depth: 20,
+            rollout_weights: RolloutWeights::default(),
+            final_move_policy: FinalMovePolicy::MaxVisits,
+            temperature: 1.0,
+            dirichlet_alpha: 0.3,
+            dirichlet_epsilon: 0.0,
+            transpositions: false,
+            solver: false,
+            max_nodes: None,
+            max_memory_bytes: None,
+            prune: true,
+            progressive_bias: 0.0,
+            seed: None,
+        }
+    }
+}
+
+impl MctsConfig {
+    // RNG for one tree or thread of a search. With a seed, each stream gets its
+    // own reproducible sequence so parallel trees don't mirror each other.
+    pub fn rng(&self, stream: u64) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
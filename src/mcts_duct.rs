@@ -0,0 +1,471 @@
+use crate::mcts_config::{MctsConfig, SimultaneousPolicy};
+use crate::rollout::{rollout, sample_branch};
+use crate::valid_moves::ValidMoves;
+use poke_engine::{
+    evaluate::evaluate,
+    generate_instructions::generate_instructions_from_move_pair,
+    instruction::StateInstructions,
+    state::{MoveChoice, Side, State},
+};
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Thread-local RNG
+thread_local! {
+    static THREAD_RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+fn sigmoid(x: f32, scale: f32) -> f32 {
+    1.0 / (1.0 + (-scale * x).exp())
+}
+
+// Per-side statistics for one move at a node. Values are from the point of view
+// of the side making the move, so side two stores 1 - score.
+#[derive(Default)]
+pub struct ActionStats {
+    pub visits: i64,
+    pub value: f32,
+    // Exp3 reward estimate or cumulative regret, depending on the policy
+    pub score: f32,
+    // sum of the strategies played, normalised into the average strategy
+    pub strategy_sum: f32,
+}
+
+// One branch per StateInstructions outcome of a joint move, in generation order
+pub struct ChanceNode {
+    pub outcomes: Vec<Option<DuctNode>>,
+    pub percentages: Vec<f32>,
+    pub visits: i64,
+}
+
+impl ChanceNode {
+    pub fn new(instructions: &[StateInstructions]) -> Self {
+        ChanceNode {
+            outcomes: instructions.iter().map(|_| None).collect(),
+            percentages: instructions.iter().map(|i| i.percentage).collect(),
+            visits: 0,
+        }
+    }
+
+    // Probability-weighted mean of the branches expanded so far
+    pub fn weighted_value(&self) -> f32 {
+        let mut value = 0.0;
+        let mut weight = 0.0;
+
+        for (outcome, percentage) in self.outcomes.iter().zip(&self.percentages) {
+            if let Some(node) = outcome.as_ref().filter(|n| n.visits > 0) {
+                value += percentage * node.value / node.visits as f32;
+                weight += percentage;
+            }
+        }
+
+        if weight > 0.0 {
+            value / weight
+        } else {
+            0.5
+        }
+    }
+
+    fn iterate(
+        &mut self,
+        branch: usize,
+        state: &mut State,
+        root_eval: f32,
+        depth: i32,
+        max_depth_seen: &mut usize,
+        config: &MctsConfig,
+    ) -> f32 {
+        self.visits += 1;
+
+        let outcome = &mut self.outcomes[branch];
+        match outcome.as_mut() {
+            Some(child) => {
+                child.iterate(state, root_eval, max_depth_seen, config);
+            }
+            None => {
+                let mut child = DuctNode::new(depth);
+                let score = leaf_score(state, root_eval, config);
+                child.visits = 1;
+                child.value = score;
+                *max_depth_seen = (*max_depth_seen).max(depth as usize);
+                *outcome = Some(child);
+            }
+        }
+
+        self.weighted_value()
+    }
+}
+
+pub struct DuctNode {
+    pub children: HashMap<(MoveChoice, MoveChoice), DuctNode>,
+    pub chance_children: HashMap<(MoveChoice, MoveChoice), ChanceNode>,
+    pub side_one_stats: HashMap<MoveChoice, ActionStats>,
+    pub side_two_stats: HashMap<MoveChoice, ActionStats>,
+    pub visits: i64,
+    pub value: f32,
+    pub depth: i32,
+    // with chance nodes every node stands for one state, so its moves are kept
+    pub valid_moves: Option<ValidMoves>,
+}
+
+impl DuctNode {
+    pub fn new(depth: i32) -> Self {
+        DuctNode {
+            children: HashMap::new(),
+            chance_children: HashMap::new(),
+            side_one_stats: HashMap::new(),
+            side_two_stats: HashMap::new(),
+            visits: 0,
+            value: 0.0,
+            depth,
+            valid_moves: None,
+        }
+    }
+
+    // Runs one iteration below this node and returns the score from side one's view
+    fn iterate(
+        &mut self,
+        state: &mut State,
+        root_eval: f32,
+        max_depth_seen: &mut usize,
+        config: &MctsConfig,
+    ) -> f32 {
+        *max_depth_seen = (*max_depth_seen).max(self.depth as usize);
+
+        // The kept moves are lent out for the iteration and put back after
+        let moves = self
+            .valid_moves
+            .take()
+            .unwrap_or_else(|| ValidMoves::new(state));
+        let score = self.iterate_moves(&moves, state, root_eval, max_depth_seen, config);
+        if config.chance_nodes {
+            self.valid_moves = Some(moves);
+        }
+        score
+    }
+
+    // The rest of an iteration, given the valid moves in `state`
+    fn iterate_moves(
+        &mut self,
+        moves: &ValidMoves,
+        state: &mut State,
+        root_eval: f32,
+        max_depth_seen: &mut usize,
+        config: &MctsConfig,
+    ) -> f32 {
+        let (our_moves, opp_moves) = (&moves.ours, &moves.theirs);
+        if moves.is_terminal() || opp_moves.is_empty() {
+            let score = leaf_score(state, root_eval, config);
+            self.visits += 1;
+            self.value += score;
+            return score;
+        }
+
+        // Both sides choose independently, neither sees the other's choice
+        let (our_move, our_probability) = select_side_move(
+            &mut self.side_one_stats,
+            our_moves,
+            config.exploration_constant,
+            config,
+        );
+        let (opp_move, opp_probability) = select_side_move(
+            &mut self.side_two_stats,
+            opp_moves,
+            config.opponent_exploration_constant,
+            config,
+        );
+
+        let instructions = generate_instructions_from_move_pair(state, &our_move, &opp_move, true);
+        let branch = sample_instruction(&instructions);
+        state.apply_instructions(&instructions[branch].instruction_list);
+
+        let score = if config.chance_nodes {
+            let chance = self
+                .chance_children
+                .entry((our_move, opp_move))
+                .or_insert_with(|| ChanceNode::new(&instructions));
+            if chance.percentages.len() != instructions.len() {
+                *chance = ChanceNode::new(&instructions);
+            }
+
+            // Backs up the probability-weighted value, not the sampled branch's score
+            chance.iterate(
+                branch,
+                state,
+                root_eval,
+                self.depth + 1,
+                max_depth_seen,
+                config,
+            )
+        } else {
+            self.iterate_child(our_move, opp_move, state, root_eval, max_depth_seen, config)
+        };
+
+        update_side_stats(
+            &mut self.side_one_stats,
+            our_moves,
+            our_move,
+            our_probability,
+            score,
+            config.simultaneous_policy,
+        );
+        update_side_stats(
+            &mut self.side_two_stats,
+            opp_moves,
+            opp_move,
+            opp_probability,
+            1.0 - score,
+            config.simultaneous_policy,
+        );
+
+        self.visits += 1;
+        self.value += score;
+        score
+    }
+
+    // Open-loop child for a joint move: every instruction branch shares its statistics
+    fn iterate_child(
+        &mut self,
+        our_move: MoveChoice,
+        opp_move: MoveChoice,
+        state: &mut State,
+        root_eval: f32,
+        max_depth_seen: &mut usize,
+        config: &MctsConfig,
+    ) -> f32 {
+        match self.children.get_mut(&(our_move, opp_move)) {
+            Some(child) => child.iterate(state, root_eval, max_depth_seen, config),
+            None => {
+                let mut child = DuctNode::new(self.depth + 1);
+                let score = leaf_score(state, root_eval, config);
+                child.visits = 1;
+                child.value = score;
+                *max_depth_seen = (*max_depth_seen).max(child.depth as usize);
+                self.children.insert((our_move, opp_move), child);
+                score
+            }
+        }
+    }
+}
+
+fn leaf_score(state: &State, root_eval: f32, config: &MctsConfig) -> f32 {
+    let rolled_out = THREAD_RNG.with(|rng| rollout(state, config, &mut rng.borrow_mut()));
+    let state = rolled_out.as_ref().unwrap_or(state);
+
+    if state.battle_is_over() != 0.0 {
+        if state.battle_is_over() > 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    } else {
+        sigmoid(evaluate(state) - root_eval, config.sigmoid_scale)
+    }
+}
+
+// Exp3 and regret matching strategies, mixed with uniform exploration so every
+// move keeps a non-zero probability
+fn current_strategy(
+    stats: &HashMap<MoveChoice, ActionStats>,
+    moves: &[MoveChoice],
+    config: &MctsConfig,
+) -> Vec<f32> {
+    let n = moves.len() as f32;
+    let gamma = config.exploration_rate;
+    let scores = moves.iter().map(|m| stats.get(m).map_or(0.0, |s| s.score));
+
+    let weights: Vec<f32> = match config.simultaneous_policy {
+        SimultaneousPolicy::Exp3 => {
+            let eta = gamma / n;
+            let scores: Vec<f32> = scores.collect();
+            let max_score = scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            scores
+                .iter()
+                .map(|s| ((s - max_score) * eta).exp())
+                .collect()
+        }
+        SimultaneousPolicy::RegretMatching | SimultaneousPolicy::Ucb => {
+            scores.map(|s| s.max(0.0)).collect()
+        }
+    };
+
+    let total: f32 = weights.iter().sum();
+    weights
+        .iter()
+        .map(|w| {
+            let p = if total > 0.0 { w / total } else { 1.0 / n };
+            (1.0 - gamma) * p + gamma / n
+        })
+        .collect()
+}
+
+// Picks a move for one side, returning it with the probability it was played with.
+// `exploration_constant` is that side's UCB1 constant.
+fn select_side_move(
+    stats: &mut HashMap<MoveChoice, ActionStats>,
+    moves: &[MoveChoice],
+    exploration_constant: f32,
+    config: &MctsConfig,
+) -> (MoveChoice, f32) {
+    if config.simultaneous_policy == SimultaneousPolicy::Ucb {
+        if let Some(untried) = moves.iter().find(|m| !stats.contains_key(*m)) {
+            stats.insert(*untried, ActionStats::default());
+            return (*untried, 1.0);
+        }
+
+        let total_visits = moves.iter().map(|m| stats[m].visits).sum::<i64>();
+        let mut best_score = f32::NEG_INFINITY;
+        let mut best_move = moves[0];
+
+        for m in moves {
+            let s = &stats[m];
+            let score = if s.visits == 0 {
+                f32::INFINITY
+            } else {
+                s.value / s.visits as f32
+                    + (exploration_constant * (total_visits as f32).ln() / s.visits as f32).sqrt()
+            };
+
+            if score > best_score {
+                best_score = score;
+                best_move = *m;
+            }
+        }
+
+        return (best_move, 1.0);
+    }
+
+    let strategy = current_strategy(stats, moves, config);
+    for (m, p) in moves.iter().zip(&strategy) {
+        stats.entry(*m).or_default().strategy_sum += p;
+    }
+
+    let index = THREAD_RNG.with(|rng| match WeightedIndex::new(&strategy) {
+        Ok(dist) => dist.sample(&mut *rng.borrow_mut()),
+        Err(_) => 0,
+    });
+
+    (moves[index], strategy[index])
+}
+
+fn update_side_stats(
+    stats: &mut HashMap<MoveChoice, ActionStats>,
+    moves: &[MoveChoice],
+    chosen: MoveChoice,
+    probability: f32,
+    utility: f32,
+    policy: SimultaneousPolicy,
+) {
+    let chosen_stats = stats.entry(chosen).or_default();
+    chosen_stats.visits += 1;
+    chosen_stats.value += utility;
+
+    match policy {
+        SimultaneousPolicy::Ucb => {}
+        // Importance-weighted reward estimate for the sampled move
+        SimultaneousPolicy::Exp3 => chosen_stats.score += utility / probability,
+        // Sampled regret: u / p for the move played, minus u for every move
+        SimultaneousPolicy::RegretMatching => {
+            chosen_stats.score += utility / probability;
+            for m in moves {
+                stats.entry(*m).or_default().score -= utility;
+            }
+        }
+    }
+}
+
+// Returns the index of the sampled branch so it can key a chance node
+fn sample_instruction(instructions: &[StateInstructions]) -> usize {
+    THREAD_RNG.with(|rng| sample_branch(instructions, &mut rng.borrow_mut()))
+}
+
+// Average strategy for the Exp3 and regret matching policies, visit share for UCB
+fn root_strategy(
+    stats: &HashMap<MoveChoice, ActionStats>,
+    moves: &[MoveChoice],
+    side: &Side,
+    policy: SimultaneousPolicy,
+) -> Vec<(String, f32)> {
+    let weights: Vec<f32> = moves
+        .iter()
+        .map(|m| {
+            stats.get(m).map_or(0.0, |s| match policy {
+                SimultaneousPolicy::Ucb => s.visits as f32,
+                SimultaneousPolicy::Exp3 | SimultaneousPolicy::RegretMatching => s.strategy_sum,
+            })
+        })
+        .collect();
+    let total: f32 = weights.iter().sum();
+
+    moves
+        .iter()
+        .zip(weights)
+        .map(|(m, w)| (m.to_string(side), if total > 0.0 { w / total } else { 0.0 }))
+        .collect()
+}
+
+// Strategies of both sides at the root, the root value and the number of iterations
+pub type SimultaneousResult = (Vec<(String, f32)>, Vec<(String, f32)>, f32, i64);
+
+pub fn perform_simultaneous_search(
+    state: &State,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    config: &MctsConfig,
+) -> SimultaneousResult {
+    let start_time = Instant::now();
+    let mut root = DuctNode::new(0);
+    let mut max_depth_seen = 0;
+    let root_eval = evaluate(state);
+    THREAD_RNG.with(|rng| *rng.borrow_mut() = config.rng(0));
+
+    while !should_stop(&start_time, iterations, time_limit, &root, config) {
+        let mut sim_state = state.clone();
+        root.iterate(&mut sim_state, root_eval, &mut max_depth_seen, config);
+    }
+
+    let moves = ValidMoves::new(state);
+
+    (
+        root_strategy(
+            &root.side_one_stats,
+            &moves.ours,
+            &state.side_one,
+            config.simultaneous_policy,
+        ),
+        root_strategy(
+            &root.side_two_stats,
+            &moves.theirs,
+            &state.side_two,
+            config.simultaneous_policy,
+        ),
+        root.value / root.visits.max(1) as f32,
+        root.visits,
+    )
+}
+
+fn should_stop(
+    start_time: &Instant,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    root: &DuctNode,
+    config: &MctsConfig,
+) -> bool {
+    if let Some(max_iter) = iterations {
+        if root.visits >= max_iter as i64 {
+            return true;
+        }
+    }
+
+    if let Some(limit) = time_limit {
+        if start_time.elapsed() >= limit {
+            return true;
+        }
+    }
+
+    root.visits >= config.max_visits
+}
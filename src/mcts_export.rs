@@ -0,0 +1,135 @@
+use std::fmt::Write;
+
+// Snapshot of one tree node, detached from the searcher that produced it.
+// Moves are named as in the search result, in the state the exported line reaches.
+// An opponent move whose Pokemon isn't active there keeps the engine's MoveChoice,
+// e.g. "Move(M0)", next to the Pokemon it belongs to.
+pub struct ExportNode {
+    pub our_move: Option<String>,
+    pub actual_opponent_move: Option<String>,
+    pub original_active: Option<String>,
+    pub visits: i64,
+    pub value: f32,
+    pub last_simulation_score: Option<f32>,
+    // (opponent move, visits, last UCB score) from the node's opponent_move_stats
+    pub opponent_ucb: Vec<(String, i64, Option<f32>)>,
+    pub children: Vec<ExportNode>,
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_option_string(s: &Option<String>) -> String {
+    s.as_deref().map_or_else(|| "null".to_string(), json_string)
+}
+
+// NaN and infinity aren't valid JSON numbers
+fn json_number(x: f32) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_option_number(x: Option<f32>) -> String {
+    x.map_or_else(|| "null".to_string(), json_number)
+}
+
+impl ExportNode {
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "{{\"our_move\":{},\"actual_opponent_move\":{},\"original_active\":{},\"visits\":{},\"value\":{},\"last_simulation_score\":{},\"opponent_ucb\":[",
+            json_option_string(&self.our_move),
+            json_option_string(&self.actual_opponent_move),
+            json_option_string(&self.original_active),
+            self.visits,
+            json_number(self.value),
+            json_option_number(self.last_simulation_score),
+        );
+
+        for (i, (opp_move, visits, ucb)) in self.opponent_ucb.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"move\":{},\"visits\":{},\"ucb\":{}}}",
+                json_string(opp_move),
+                visits,
+                json_option_number(*ucb),
+            );
+        }
+
+        out.push_str("],\"children\":[");
+        for (i, child) in self.children.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            child.write_json(out);
+        }
+        out.push_str("]}");
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out =
+            String::from("digraph mcts {\n    node [shape=box, fontname=\"monospace\"];\n");
+        let mut next_id = 0;
+        self.write_dot(&mut out, &mut next_id);
+        out.push_str("}\n");
+        out
+    }
+
+    fn write_dot(&self, out: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+
+        let mut label = format!(
+            "our: {}\\nopp: {}\\nvisits: {}\\nvalue: {:.3}",
+            self.our_move.as_deref().unwrap_or("root"),
+            self.actual_opponent_move.as_deref().unwrap_or("-"),
+            self.visits,
+            self.value,
+        );
+        if let Some(score) = self.last_simulation_score {
+            let _ = write!(label, "\\nlast: {score:.3}");
+        }
+        for (opp_move, visits, ucb) in &self.opponent_ucb {
+            let _ = write!(label, "\\n{opp_move} n={visits}");
+            if let Some(ucb) = ucb {
+                let _ = write!(label, " ucb={ucb:.3}");
+            }
+        }
+
+        let _ = writeln!(out, "    n{id} [label=\"{}\"];", label.replace('"', "\\\""));
+
+        for child in &self.children {
+            let child_id = child.write_dot(out, next_id);
+            let _ = writeln!(out, "    n{id} -> n{child_id};");
+        }
+
+        id
+    }
+}
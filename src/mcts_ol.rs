@@ -1,28 +1,34 @@
+use crate::mcts_config::{MctsConfig, ParallelMode};
+use crate::mcts_export::ExportNode;
+use crate::mcts_result::{root_move_stats, SearchDiagnostics, SearchResult};
+use crate::rollout::{rollout, sample_branch};
+use crate::valid_moves::ValidMoves;
 use poke_engine::{
     evaluate::evaluate,
     generate_instructions::generate_instructions_from_move_pair,
-    instruction::StateInstructions,
+    instruction::{Instruction, StateInstructions},
     pokemon::PokemonName,
-    state::{MoveChoice, State},
+    state::{MoveChoice, Side, State},
 };
-use rand::distributions::WeightedIndex;
 use rand::prelude::*;
+use rand::rngs::StdRng;
 use rayon::prelude::*;
+use rayon::ThreadPool;
 use smallvec::SmallVec;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 // Thread-local RNG
 thread_local! {
-    static THREAD_RNG: RefCell<ThreadRng> = RefCell::new(thread_rng());
+    static THREAD_RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
 }
 
-fn sigmoid(x: f32) -> f32 {
-    // Tuned so that ~200 points is very close to 1.0
-    1.0 / (1.0 + (-0.0125 * x).exp())
+fn sigmoid(x: f32, scale: f32) -> f32 {
+    1.0 / (1.0 + (-scale * x).exp())
 }
 
 #[derive(Clone, Hash, Eq, PartialEq)]
@@ -32,6 +38,17 @@ pub struct UniqueMove {
     is_switch: bool,
 }
 
+impl UniqueMove {
+    // Named in `side` while its Pokemon is active there, else as the engine's MoveChoice
+    fn describe(&self, side: &Side) -> String {
+        if side.get_active_immutable().id == self.pokemon_name {
+            format!("{} {}", self.pokemon_name, self.move_choice.to_string(side))
+        } else {
+            format!("{} {:?}", self.pokemon_name, self.move_choice)
+        }
+    }
+}
+
 pub struct OpponentMoveStats {
     pub visits: i64,
     pub value: f32,
@@ -43,26 +60,652 @@ pub struct OpponentMoveStats {
 pub struct MoveHistoryEntry {
     opp_move: MoveChoice,
     opp_active: PokemonName, // Active at time of move
+    // the branch applied at this step, reversed to walk the state back to the root
+    instructions: Vec<Instruction>,
+}
+
+impl MoveHistoryEntry {
+    fn unique_move(&self) -> UniqueMove {
+        UniqueMove {
+            move_choice: self.opp_move,
+            pokemon_name: self.opp_active,
+            is_switch: matches!(self.opp_move, MoveChoice::Switch(_)),
+        }
+    }
 }
 
+// Index of a node in its tree's arena
+pub type NodeIndex = u32;
+
+const ROOT: NodeIndex = 0;
+
+// Nodes live in one flat arena per tree and refer to each other by index, so a
+// search thread owns its whole tree and never locks or reference counts a node
 pub struct MCTS {
-    root: Arc<Mutex<MCTSNode>>,
-    max_depth_seen: Arc<Mutex<usize>>,
+    nodes: Vec<MCTSNode>,
+    max_depth_seen: usize,
+    // no new nodes are added past this many, set per search from the config
+    node_budget: Option<usize>,
 }
 
+// Rough size of a node with a typical handful of children and opponent moves, used to
+// turn a byte budget into a node budget
+const ESTIMATED_NODE_BYTES: usize = size_of::<MCTSNode>()
+    + 4 * size_of::<(MoveChoice, NodeIndex)>()
+    + 4 * size_of::<(UniqueMove, OpponentMoveStats)>()
+    + size_of::<ValidMoves>()
+    + 8 * size_of::<MoveChoice>();
+
 impl MCTS {
     pub fn new() -> Self {
         MCTS {
-            root: Arc::new(Mutex::new(MCTSNode::new(0))), // Only one Arc
-            max_depth_seen: Arc::new(Mutex::new(0)),
+            nodes: vec![MCTSNode::new(0)],
+            max_depth_seen: 0,
+            node_budget: None,
+        }
+    }
+
+    // Full once the arena's capacity, not just its length, has reached the budget
+    fn is_full(&self) -> bool {
+        self.node_budget.is_some_and(|budget| {
+            self.nodes.len() >= self.nodes.capacity() && self.nodes.capacity() >= budget
+        })
+    }
+
+    // Sizes the arena to the search's budget up front, pruning a tree that's already
+    // over it, so the arena never reallocates past the budget mid-search
+    fn set_node_budget(&mut self, budget: Option<usize>) {
+        self.node_budget = budget;
+        let Some(budget) = budget else {
+            return;
+        };
+
+        self.prune(budget);
+        if self.nodes.capacity() > budget {
+            self.nodes.shrink_to(budget);
+        } else {
+            self.nodes.reserve_exact(budget - self.nodes.len());
+        }
+    }
+
+    // Heap memory held by the tree, counting allocated rather than used capacity
+    pub fn memory_bytes(&self) -> usize {
+        self.nodes.capacity() * size_of::<MCTSNode>()
+            + self
+                .nodes
+                .iter()
+                .map(|node| {
+                    node.children.capacity() * size_of::<(MoveChoice, NodeIndex)>()
+                        + node.opponent_move_stats.capacity()
+                            * size_of::<(UniqueMove, OpponentMoveStats)>()
+                        + node
+                            .valid_moves
+                            .as_ref()
+                            .map_or(0, |moves| moves.memory_bytes())
+                })
+                .sum::<usize>()
+    }
+
+    pub fn root(&self) -> &MCTSNode {
+        &self.nodes[ROOT as usize]
+    }
+
+    fn node(&self, index: NodeIndex) -> &MCTSNode {
+        &self.nodes[index as usize]
+    }
+
+    fn node_mut(&mut self, index: NodeIndex) -> &mut MCTSNode {
+        &mut self.nodes[index as usize]
+    }
+
+    fn add_node(&mut self, node: MCTSNode) -> NodeIndex {
+        // Under a budget the arena grows by at most doubling and never past the budget,
+        // rather than leaving it to Vec's growth
+        if let Some(budget) = self.node_budget {
+            if self.nodes.len() == self.nodes.capacity() {
+                let room = budget.saturating_sub(self.nodes.len()).max(1);
+                self.nodes
+                    .reserve_exact(self.nodes.capacity().max(1).min(room));
+            }
+        }
+        self.nodes.push(node);
+        (self.nodes.len() - 1) as NodeIndex
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Promotes the child for the joint move and observed instruction branch to be the
+    // new root, dropping the rest of the tree. The tree is open-loop, so this is only
+    // exact when every visit to the child came through that same reply and branch.
+    // Otherwise its statistics belong to a mix of states and a fresh tree is started.
+    pub fn advance(&mut self, our_move: &MoveChoice, opp_move: &MoveChoice, branch: usize) {
+        let child = self
+            .root()
+            .child(our_move)
+            .filter(|child| self.node(*child).origin == Some((*opp_move, branch)));
+        let Some(child) = child else {
+            *self = MCTS::new();
+            return;
+        };
+
+        self.compact(child, 1, 0, |_| true);
+        self.nodes[ROOT as usize].our_move = None;
+        self.nodes[ROOT as usize].actual_opponent_move = None;
+        self.nodes[ROOT as usize].origin = None;
+        self.max_depth_seen = 0;
+    }
+
+    // Drops the least visited subtrees until at most `target` nodes are left. A child
+    // never has more visits than its parent, so keeping every node above a visit
+    // threshold keeps the tree connected. Only safe while no iteration is in flight.
+    fn prune(&mut self, target: usize) {
+        if self.nodes.len() <= target.max(1) {
+            return;
+        }
+
+        let mut visits: Vec<i64> = self.nodes.iter().map(|node| node.visits).collect();
+        let cut = self.nodes.len() - target.max(1);
+        let threshold = *visits.select_nth_unstable(cut).1;
+        self.compact(ROOT, 0, target.max(1), |node| node.visits > threshold);
+    }
+
+    // Rebuilds the arena from `new_root` down, breadth first so it stays compact,
+    // keeping only the children `keep` accepts. A child's new index is handed out
+    // when it's queued. The new arena starts with room for `capacity` nodes.
+    fn compact(
+        &mut self,
+        new_root: NodeIndex,
+        depth_shift: i32,
+        capacity: usize,
+        keep: impl Fn(&MCTSNode) -> bool,
+    ) {
+        let mut nodes = Vec::with_capacity(capacity);
+        let mut next_index: NodeIndex = 1;
+        let mut queue = VecDeque::from([(new_root, None)]);
+
+        while let Some((old_index, parent)) = queue.pop_front() {
+            let mut node = std::mem::replace(self.node_mut(old_index), MCTSNode::new(0));
+            let new_index = nodes.len() as NodeIndex;
+            node.parent = parent;
+            node.depth -= depth_shift;
+
+            node.children
+                .retain(|(_, child_index)| keep(self.node(*child_index)));
+            for (_, child_index) in &mut node.children {
+                queue.push_back((*child_index, Some(new_index)));
+                *child_index = next_index;
+                next_index += 1;
+            }
+            nodes.push(node);
+        }
+
+        self.nodes = nodes;
+    }
+
+    // Snapshot of a node reached in `state` and `plies` levels below it, children
+    // sorted by visits. Moves are named in the state they were chosen in, so the
+    // node's own are left for its parent to fill in.
+    fn export(&self, index: NodeIndex, state: &mut State, plies: usize) -> ExportNode {
+        let node = self.node(index);
+
+        let mut children: Vec<_> = if plies > 0 {
+            node.children
+                .iter()
+                .map(|(our_move, child)| self.export_child(*our_move, *child, state, plies - 1))
+                .collect()
+        } else {
+            vec![]
+        };
+        children.sort_by_key(|child| std::cmp::Reverse(child.visits));
+
+        let mut opponent_ucb: Vec<_> = node
+            .opponent_move_stats
+            .iter()
+            .map(|(unique_move, stats)| {
+                (
+                    unique_move.describe(&state.side_two),
+                    stats.visits,
+                    stats.last_ucb,
+                )
+            })
+            .collect();
+        opponent_ucb.sort_by_key(|(_, visits, _)| std::cmp::Reverse(*visits));
+
+        ExportNode {
+            our_move: None,
+            actual_opponent_move: None,
+            original_active: node.original_active.map(|p| p.to_string()),
+            visits: node.visits,
+            value: node.value / node.visits.max(1) as f32,
+            last_simulation_score: node.last_simulation_score,
+            opponent_ucb,
+            children,
+        }
+    }
+
+    // Exports a child of the node reached in `state`, from the state one joint move on:
+    // the reply and branch every visit came through, or else the reply it was expanded
+    // with and its most likely branch, as in the principal variation
+    fn export_child(
+        &self,
+        our_move: MoveChoice,
+        child: NodeIndex,
+        state: &mut State,
+        plies: usize,
+    ) -> ExportNode {
+        let node = self.node(child);
+        let opp_active = state.side_two.get_active_immutable().id;
+        let (opp_move, branch) = match (node.origin, &node.actual_opponent_move) {
+            (Some((opp_move, branch)), _) => (opp_move, Some(branch)),
+            (None, Some(reply)) if reply.pokemon_name == opp_active => (reply.move_choice, None),
+            (None, _) => (MoveChoice::None, None),
+        };
+        let our_label = our_move.to_string(&state.side_one);
+        let opp_label = node
+            .actual_opponent_move
+            .as_ref()
+            .map(|reply| reply.describe(&state.side_two));
+
+        let instructions = generate_instructions_from_move_pair(state, &our_move, &opp_move, true);
+        let chosen = branch.and_then(|b| instructions.get(b)).or_else(|| {
+            instructions
+                .iter()
+                .max_by(|a, b| a.percentage.total_cmp(&b.percentage))
+        });
+
+        let mut export = match chosen {
+            Some(chosen) => {
+                state.apply_instructions(&chosen.instruction_list);
+                let export = self.export(child, state, plies);
+                state.reverse_instructions(&chosen.instruction_list);
+                export
+            }
+            None => self.export(child, state, plies),
+        };
+        export.our_move = Some(our_label);
+        export.actual_opponent_move = opp_label;
+        export
+    }
+
+    // Decides what selection does at `current`: stop at a leaf, try an untried move
+    // of ours, or follow the best UCB child. The opponent's reply is picked here too.
+    fn choose_step(
+        &mut self,
+        current: NodeIndex,
+        state: &State,
+        valid_moves: &ValidMoves,
+        config: &MctsConfig,
+    ) -> SelectionStep {
+        if valid_moves.is_terminal() {
+            return SelectionStep::Leaf;
+        }
+
+        let valid_our_moves = valid_moves.ours.as_slice();
+        let valid_opp_moves = valid_moves.theirs.as_slice();
+
+        // Check for untried moves, a full tree only follows the children it has
+        let untried_move = if self.is_full() {
+            None
+        } else {
+            valid_our_moves
+                .iter()
+                .find(|m| self.node(current).child(m).is_none())
+                .copied()
+        };
+
+        let (our_move, child) = match untried_move {
+            Some(our_move) => (our_move, None),
+            None => {
+                // Selection phase
+                let node = self.node(current);
+                let mut best_score = f32::NEG_INFINITY;
+                let mut best = None;
+
+                for (move_choice, child) in &node.children {
+                    if valid_our_moves.contains(move_choice) {
+                        let child_node = self.node(*child);
+                        let score = child_node.ucb1_score(node.visits, config.exploration_constant)
+                            + valid_moves.bias(move_choice, child_node.visits, config);
+
+                        if score > best_score {
+                            best_score = score;
+                            best = Some((*move_choice, *child));
+                        }
+                    }
+                }
+
+                match best {
+                    Some((our_move, child)) => (our_move, Some(child)),
+                    None => return SelectionStep::Leaf,
+                }
+            }
+        };
+
+        let opp_move = if valid_opp_moves
+            .iter()
+            .all(|m| matches!(m, MoveChoice::Switch(_)))
+        {
+            valid_opp_moves[0]
+        } else {
+            self.node_mut(current).select_opponent_move(
+                valid_opp_moves,
+                state,
+                config.opponent_exploration_constant,
+            )
+        };
+
+        match child {
+            Some(child) => SelectionStep::Descend {
+                our_move,
+                opp_move,
+                child,
+            },
+            None => SelectionStep::Expand { our_move, opp_move },
+        }
+    }
+
+    // Adds the child for our move, or returns it if another thread got there first.
+    // `origin` is the opponent's reply and the instruction branch that led to it.
+    fn add_child(
+        &mut self,
+        parent: NodeIndex,
+        our_move: MoveChoice,
+        opp_move: UniqueMove,
+        origin: (MoveChoice, usize),
+        our_active: PokemonName,
+    ) -> NodeIndex {
+        if let Some(child) = self.node(parent).child(&our_move) {
+            self.record_visit_origin(child, opp_move, origin);
+            return child;
+        }
+
+        let mut new_node = MCTSNode::new(self.node(parent).depth + 1);
+        new_node.our_move = Some(our_move);
+        new_node.parent = Some(parent);
+        new_node.actual_opponent_move = Some(opp_move);
+        new_node.origin = Some(origin);
+        new_node.original_active = Some(our_active);
+
+        let new_index = self.add_node(new_node);
+        self.node_mut(parent).children.push((our_move, new_index));
+        new_index
+    }
+
+    // Notes how selection reached an existing child, forgetting its origin if it differs
+    fn record_visit_origin(
+        &mut self,
+        child: NodeIndex,
+        opp_move: UniqueMove,
+        origin: (MoveChoice, usize),
+    ) {
+        let node = self.node_mut(child);
+        node.actual_opponent_move = Some(opp_move);
+        if node.origin != Some(origin) {
+            node.origin = None;
+        }
+    }
+
+    // `root_moves` are the valid moves of the root state, generated once per search
+    // Valid moves at an exact node, generated on its first visit and kept after
+    fn exact_node_moves(&mut self, node: NodeIndex, state: &State) -> Arc<ValidMoves> {
+        let moves = self
+            .node_mut(node)
+            .valid_moves
+            .get_or_insert_with(|| Arc::new(ValidMoves::new(state)));
+        Arc::clone(moves)
+    }
+
+    fn select_and_expand(
+        &mut self,
+        state: &mut State,
+        root_moves: &ValidMoves,
+        config: &MctsConfig,
+    ) -> (NodeIndex, SmallVec<[MoveHistoryEntry; 16]>) {
+        let mut current_node = ROOT;
+        let mut move_history = SmallVec::new();
+        // whether every node so far has only been reached through this line
+        let mut exact = true;
+
+        loop {
+            // Update max depth seen
+            self.max_depth_seen = self
+                .max_depth_seen
+                .max(self.node(current_node).depth as usize);
+
+            let cached_moves;
+            let fresh_moves;
+            let valid_moves: &ValidMoves = if current_node == ROOT {
+                root_moves
+            } else if exact {
+                cached_moves = self.exact_node_moves(current_node, state);
+                &cached_moves
+            } else {
+                // a node reached through a second line never is exact again
+                self.node_mut(current_node).valid_moves = None;
+                fresh_moves = ValidMoves::new(state);
+                &fresh_moves
+            };
+            let (our_move, opp_move, child) =
+                match self.choose_step(current_node, state, valid_moves, config) {
+                    SelectionStep::Leaf => return (current_node, move_history),
+                    SelectionStep::Expand { our_move, opp_move } => (our_move, opp_move, None),
+                    SelectionStep::Descend {
+                        our_move,
+                        opp_move,
+                        child,
+                    } => (our_move, opp_move, Some(child)),
+                };
+
+            // Store the active Pokemon at time of move, before applying anything
+            let opp_active = state.side_two.get_active_immutable().id;
+            let (branch, instructions) = sample_instruction(generate_instructions_from_move_pair(
+                state, &our_move, &opp_move, true,
+            ));
+            state.apply_instructions(&instructions);
+
+            let entry = MoveHistoryEntry {
+                opp_move,
+                opp_active,
+                instructions,
+            };
+            let unique_move = entry.unique_move();
+            move_history.push(entry);
+
+            match child {
+                None => {
+                    let our_active = state.side_one.get_active_immutable().id;
+                    let new_node = self.add_child(
+                        current_node,
+                        our_move,
+                        unique_move,
+                        (opp_move, branch),
+                        our_active,
+                    );
+                    return (new_node, move_history);
+                }
+                Some(child) => {
+                    // Update node with the actual opponent move
+                    self.record_visit_origin(child, unique_move, (opp_move, branch));
+                    exact = exact && self.node(child).origin.is_some();
+                    current_node = child;
+                }
+            }
+        }
+    }
+
+    // Counts an iteration as a loss for whoever is choosing before its result is known,
+    // so other threads of a shared tree spread out instead of following the same line
+    fn add_virtual_loss(
+        &mut self,
+        parent: NodeIndex,
+        child: Option<NodeIndex>,
+        opp_move: UniqueMove,
+    ) {
+        if let Some(child) = child {
+            self.node_mut(child).visits += 1;
+        }
+        let stats = self.node_mut(parent).opponent_stats_mut(opp_move);
+        stats.visits += 1;
+        stats.value += 1.0;
+    }
+
+    // With `virtual_loss`, visits were already counted during selection and the
+    // opponent's virtual loss is taken back
+    fn backpropagate(
+        &mut self,
+        node: NodeIndex,
+        score: f32,
+        move_history: &[MoveHistoryEntry],
+        virtual_loss: bool,
+    ) {
+        let visit = i64::from(!virtual_loss);
+        let opponent_correction = if virtual_loss { 1.0 } else { 0.0 };
+        let mut current = node;
+
+        // Update the leaf node
+        {
+            let leaf = self.node_mut(current);
+            leaf.visits += visit;
+            leaf.value += score;
+            leaf.last_simulation_score = Some(score);
+        }
+
+        // Walk back up the tree
+        for entry in move_history.iter().rev() {
+            let Some(parent) = self.node(current).parent else {
+                break;
+            };
+
+            let parent_node = self.node_mut(parent);
+            parent_node.visits += visit;
+            parent_node.value += score;
+
+            // Update opponent stats using move history entry
+            let stats = parent_node.opponent_stats_mut(entry.unique_move());
+            stats.visits += visit;
+            stats.value += score - opponent_correction;
+
+            current = parent;
+        }
+    }
+}
+
+// What selection does at one node
+enum SelectionStep {
+    Leaf,
+    Expand {
+        our_move: MoveChoice,
+        opp_move: MoveChoice,
+    },
+    Descend {
+        our_move: MoveChoice,
+        opp_move: MoveChoice,
+        child: NodeIndex,
+    },
+}
+
+// Tree-parallel counterpart of select_and_expand, the lock is only held while
+// choosing moves and updating nodes, never while generating instructions
+fn select_and_expand_shared(
+    tree: &Mutex<MCTS>,
+    state: &mut State,
+    root_moves: &ValidMoves,
+    config: &MctsConfig,
+) -> (NodeIndex, SmallVec<[MoveHistoryEntry; 16]>) {
+    let mut current_node = ROOT;
+    let mut move_history = SmallVec::new();
+    let mut exact = true;
+    tree.lock().unwrap().node_mut(ROOT).visits += 1;
+
+    loop {
+        // An exact node's kept moves are read under the lock, any others are generated
+        // before taking it
+        let cached_moves = if current_node != ROOT && exact {
+            tree.lock().unwrap().node(current_node).valid_moves.clone()
+        } else {
+            None
+        };
+        let fresh_moves = (current_node != ROOT && cached_moves.is_none())
+            .then(|| Arc::new(ValidMoves::new(state)));
+        let valid_moves: &ValidMoves = match cached_moves.as_ref().or(fresh_moves.as_ref()) {
+            Some(moves) => moves,
+            None => root_moves,
+        };
+        let opp_active = state.side_two.get_active_immutable().id;
+
+        let (our_move, opp_move, child) = {
+            let mut tree = tree.lock().unwrap();
+            tree.max_depth_seen = tree
+                .max_depth_seen
+                .max(tree.node(current_node).depth as usize);
+            if current_node != ROOT && cached_moves.is_none() {
+                tree.node_mut(current_node).valid_moves = fresh_moves.clone().filter(|_| exact);
+            }
+
+            let (our_move, opp_move, child) =
+                match tree.choose_step(current_node, state, valid_moves, config) {
+                    SelectionStep::Leaf => return (current_node, move_history),
+                    SelectionStep::Expand { our_move, opp_move } => (our_move, opp_move, None),
+                    SelectionStep::Descend {
+                        our_move,
+                        opp_move,
+                        child,
+                    } => (our_move, opp_move, Some(child)),
+                };
+
+            let unique_move = UniqueMove {
+                move_choice: opp_move,
+                pokemon_name: opp_active,
+                is_switch: matches!(opp_move, MoveChoice::Switch(_)),
+            };
+            tree.add_virtual_loss(current_node, child, unique_move);
+            (our_move, opp_move, child)
+        };
+
+        let (branch, instructions) = sample_instruction(generate_instructions_from_move_pair(
+            state, &our_move, &opp_move, true,
+        ));
+        state.apply_instructions(&instructions);
+
+        let entry = MoveHistoryEntry {
+            opp_move,
+            opp_active,
+            instructions,
+        };
+        let unique_move = entry.unique_move();
+        move_history.push(entry);
+
+        let mut tree = tree.lock().unwrap();
+        match child {
+            None => {
+                let our_active = state.side_one.get_active_immutable().id;
+                let new_node = tree.add_child(
+                    current_node,
+                    our_move,
+                    unique_move,
+                    (opp_move, branch),
+                    our_active,
+                );
+                tree.node_mut(new_node).visits += 1;
+                return (new_node, move_history);
+            }
+            Some(child) => {
+                tree.record_visit_origin(child, unique_move, (opp_move, branch));
+                exact = exact && tree.node(child).origin.is_some();
+                current_node = child;
+            }
         }
     }
 }
 
 pub struct MCTSNode {
-    pub parent: Option<std::sync::Weak<Mutex<MCTSNode>>>,
-    pub children: HashMap<MoveChoice, Arc<Mutex<MCTSNode>>>,
-    pub opponent_move_stats: HashMap<UniqueMove, OpponentMoveStats>,
+    pub parent: Option<NodeIndex>,
+    // a node rarely has more than a handful of children or opponent moves,
+    // so these are scanned linearly rather than hashed
+    pub children: Vec<(MoveChoice, NodeIndex)>,
+    pub opponent_move_stats: Vec<(UniqueMove, OpponentMoveStats)>,
     pub visits: i64,
     pub value: f32,
     pub our_move: Option<MoveChoice>,
@@ -70,14 +713,20 @@ pub struct MCTSNode {
     pub last_simulation_score: Option<f32>,
     pub actual_opponent_move: Option<UniqueMove>,
     pub original_active: Option<PokemonName>, // Store active Pokemon at time node was created
+    // opponent reply and instruction branch every visit here came through from the
+    // parent, None once they've differed or at the root
+    pub origin: Option<(MoveChoice, usize)>,
+    // moves of the one state an exact node is reached in, kept from its first visit.
+    // A node is exact while it and every node above it, bar the root, have an origin.
+    pub valid_moves: Option<Arc<ValidMoves>>,
 }
 
 impl MCTSNode {
     pub fn new(depth: i32) -> Self {
         MCTSNode {
             parent: None,
-            children: HashMap::new(),
-            opponent_move_stats: HashMap::new(),
+            children: Vec::new(),
+            opponent_move_stats: Vec::new(),
             visits: 0,
             value: 0.0,
             our_move: None,
@@ -85,17 +734,54 @@ impl MCTSNode {
             last_simulation_score: None,
             actual_opponent_move: None,
             original_active: None,
+            origin: None,
+            valid_moves: None,
         }
     }
 
+    pub fn child(&self, our_move: &MoveChoice) -> Option<NodeIndex> {
+        self.children
+            .iter()
+            .find(|(mov, _)| mov == our_move)
+            .map(|(_, child)| *child)
+    }
+
+    fn has_opponent_stats(&self, unique_move: &UniqueMove) -> bool {
+        self.opponent_move_stats
+            .iter()
+            .any(|(existing, _)| existing == unique_move)
+    }
+
+    fn opponent_stats_mut(&mut self, unique_move: UniqueMove) -> &mut OpponentMoveStats {
+        let position = match self
+            .opponent_move_stats
+            .iter()
+            .position(|(existing, _)| *existing == unique_move)
+        {
+            Some(position) => position,
+            None => {
+                self.opponent_move_stats.push((
+                    unique_move,
+                    OpponentMoveStats {
+                        visits: 0,
+                        value: 0.0,
+                        last_ucb: None,
+                    },
+                ));
+                self.opponent_move_stats.len() - 1
+            }
+        };
+        &mut self.opponent_move_stats[position].1
+    }
+
     #[inline(always)]
-    pub fn ucb1_score(&self, parent_visits: i64) -> f32 {
+    pub fn ucb1_score(&self, parent_visits: i64, exploration_constant: f32) -> f32 {
         if self.visits == 0 {
             return f32::INFINITY;
         }
         let visits_f = self.visits as f32;
         let exploit = self.value / visits_f;
-        let explore = (2.0 * (parent_visits as f32).ln() / visits_f).sqrt();
+        let explore = (exploration_constant * (parent_visits as f32).ln() / visits_f).sqrt();
         exploit + explore
     }
 
@@ -103,6 +789,7 @@ impl MCTSNode {
         &mut self,
         available_moves: &[MoveChoice],
         state: &State,
+        exploration_constant: f32,
     ) -> MoveChoice {
         let current_opponent_active = state.side_two.get_active_immutable().id;
 
@@ -115,26 +802,20 @@ impl MCTSNode {
                     pokemon_name: current_opponent_active,
                     is_switch: matches!(m, MoveChoice::Switch(_)),
                 };
-                !self.opponent_move_stats.contains_key(&unique_move)
+                !self.has_opponent_stats(&unique_move)
             })
             .collect();
 
         if !untried_moves.is_empty() {
-            let chosen = untried_moves[thread_rng().gen_range(0..untried_moves.len())].clone();
+            let chosen = THREAD_RNG
+                .with(|rng| *untried_moves[rng.borrow_mut().gen_range(0..untried_moves.len())]);
             let unique_move = UniqueMove {
                 move_choice: chosen.clone(),
                 pokemon_name: current_opponent_active,
                 is_switch: matches!(chosen, MoveChoice::Switch(_)),
             };
 
-            self.opponent_move_stats.insert(
-                unique_move,
-                OpponentMoveStats {
-                    visits: 0,
-                    value: 0.0,
-                    last_ucb: None,
-                },
-            );
+            self.opponent_stats_mut(unique_move);
 
             return chosen;
         }
@@ -142,8 +823,8 @@ impl MCTSNode {
         // Calculate UCB1 scores
         let total_visits = self
             .opponent_move_stats
-            .values()
-            .map(|stats| stats.visits)
+            .iter()
+            .map(|(_, stats)| stats.visits)
             .sum::<i64>();
 
         let mut best_score = f32::NEG_INFINITY;
@@ -156,9 +837,15 @@ impl MCTSNode {
                 is_switch: matches!(move_choice, MoveChoice::Switch(_)),
             };
 
-            if let Some(stats) = self.opponent_move_stats.get_mut(&unique_move) {
+            if let Some((_, stats)) = self
+                .opponent_move_stats
+                .iter_mut()
+                .find(|(existing, _)| *existing == unique_move)
+            {
                 let exploitation = 1.0 - (stats.value / stats.visits as f32); // Convert to opponent value
-                let exploration = (2.0 * (total_visits as f32).ln() / stats.visits as f32).sqrt();
+                let exploration = (exploration_constant * (total_visits as f32).ln()
+                    / stats.visits as f32)
+                    .sqrt();
                 let ucb_score = exploitation + exploration; // Add exploration since we're maximizing
                 stats.last_ucb = Some(ucb_score);
 
@@ -171,377 +858,418 @@ impl MCTSNode {
 
         best_move.unwrap_or_else(|| available_moves[0].clone())
     }
+}
 
-    fn select_and_expand(
-        node: Arc<Mutex<MCTSNode>>,
-        state: &mut State,
-        max_depth_seen: &Arc<Mutex<usize>>,
-    ) -> (Arc<Mutex<MCTSNode>>, SmallVec<[MoveHistoryEntry; 16]>) {
-        let mut current_node = node;
-        let mut move_history = SmallVec::new();
+// Picks one branch by its probability and takes its instructions, along with its index
+fn sample_instruction(mut instructions: Vec<StateInstructions>) -> (usize, Vec<Instruction>) {
+    let index = THREAD_RNG.with(|rng| sample_branch(&instructions, &mut rng.borrow_mut()));
+    (index, instructions.swap_remove(index).instruction_list)
+}
 
-        loop {
-            // Update max depth seen
-            {
-                let node_guard = current_node.lock().unwrap();
-                let mut depth_seen = max_depth_seen.lock().unwrap();
-                *depth_seen = (*depth_seen).max(node_guard.depth as usize);
-            }
+// Reverses a selection path's instructions, leaving `state` as it was at the root
+fn unwind(state: &mut State, move_history: &[MoveHistoryEntry]) {
+    for entry in move_history.iter().rev() {
+        state.reverse_instructions(&entry.instructions);
+    }
+}
 
-            // Get all possible moves
-            let (our_moves, opp_moves) = state.get_all_options();
-            // Check if game is over, either by battle outcome or no valid moves
-            if state.battle_is_over() != 0.0 || (our_moves.is_empty() && opp_moves.is_empty()) {
-                return (current_node, move_history);
-            }
+// Runs `f` on a dedicated pool, or on the global rayon pool without one.
+// Everything rayon does inside, including current_num_threads, uses that pool.
+pub fn run_in_pool<R: Send>(pool: Option<&ThreadPool>, f: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
 
-            // Truly game over - no moves available for either side
-            if our_moves.is_empty() && opp_moves.is_empty() {
-                let is_root = {
-                    let node_guard = current_node.lock().unwrap();
-                    node_guard.parent.is_none()
-                };
-                if !is_root {
-                    return (current_node, move_history);
-                }
+// The opponent's replies at the root, most visited first across all trees
+pub fn likely_replies(trees: &[MCTS]) -> Vec<MoveChoice> {
+    let mut replies: Vec<(MoveChoice, i64)> = Vec::new();
+    for tree in trees {
+        for (unique_move, stats) in &tree.root().opponent_move_stats {
+            match replies
+                .iter_mut()
+                .find(|(m, _)| *m == unique_move.move_choice)
+            {
+                Some((_, visits)) => *visits += stats.visits,
+                None => replies.push((unique_move.move_choice, stats.visits)),
             }
+        }
+    }
+    replies.sort_by_key(|(_, visits)| std::cmp::Reverse(*visits));
+    replies.into_iter().map(|(m, _)| m).collect()
+}
 
-            // Handle valid moves based on current state
-            let valid_our_moves = if our_moves.contains(&MoveChoice::None) {
-                vec![MoveChoice::None]
-            } else {
-                our_moves
-                    .iter()
-                    .filter(|&m| !matches!(m, MoveChoice::None))
-                    .cloned()
-                    .collect::<Vec<_>>()
-            };
-
-            let valid_opp_moves = if opp_moves.contains(&MoveChoice::None) {
-                let switch_moves: Vec<_> = opp_moves
-                    .iter()
-                    .filter(|&m| matches!(m, MoveChoice::Switch(_)))
-                    .cloned()
-                    .collect();
-                if !switch_moves.is_empty() {
-                    switch_moves
-                } else {
-                    vec![MoveChoice::None]
-                }
-            } else if our_moves.iter().all(|m| matches!(m, MoveChoice::Switch(_))) {
-                vec![MoveChoice::None]
-            } else {
-                opp_moves
-                    .iter()
-                    .filter(|&m| !matches!(m, MoveChoice::None))
-                    .cloned()
-                    .collect::<Vec<_>>()
-            };
+// Root parallelism gives each thread its own tree, tree parallelism shares one
+pub fn new_trees(config: &MctsConfig) -> Vec<MCTS> {
+    let n_trees = match config.parallel_mode {
+        ParallelMode::Root => rayon::current_num_threads(),
+        ParallelMode::Tree => 1,
+    };
+    (0..n_trees).map(|_| MCTS::new()).collect()
+}
 
-            // Check for untried moves
-            let (untried_move, has_untried) = {
-                let node_guard = current_node.lock().unwrap();
-                let untried = valid_our_moves
-                    .iter()
-                    .find(|m| !node_guard.children.contains_key(*m))
-                    .cloned();
-                (untried, untried.is_some())
-            };
+fn simulation_score(sim_state: &State, root_eval: f32, config: &MctsConfig) -> f32 {
+    let rolled_out = THREAD_RNG.with(|rng| rollout(sim_state, config, &mut rng.borrow_mut()));
+    let sim_state = rolled_out.as_ref().unwrap_or(sim_state);
 
-            if has_untried {
-                let our_move = untried_move.unwrap();
+    if sim_state.battle_is_over() != 0.0 {
+        if sim_state.battle_is_over() > 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    } else {
+        sigmoid(evaluate(sim_state) - root_eval, config.sigmoid_scale)
+    }
+}
 
-                let opp_move = if valid_opp_moves
-                    .iter()
-                    .all(|m| matches!(m, MoveChoice::Switch(_)))
-                {
-                    valid_opp_moves[0].clone()
-                } else {
-                    current_node
-                        .lock()
-                        .unwrap()
-                        .select_opponent_move(&valid_opp_moves, state)
-                };
+pub fn perform_mcts_search(
+    state: &mut State,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    config: &MctsConfig,
+    export_depth: Option<usize>,
+) -> SearchResult {
+    let mut trees = new_trees(config);
+
+    let diagnostics = search_trees(
+        &mut trees,
+        state,
+        iterations,
+        time_limit,
+        config,
+        &AtomicBool::new(false),
+    );
+
+    let mut result = choose_best_move(&trees, state, config);
+    result.diagnostics = diagnostics;
+
+    if let Some(plies) = export_depth {
+        export_tree(&mut result, &trees, state, plies);
+    }
+    result
+}
 
-                // In select_and_expand, before applying any moves:
-                let current_opponent_active = state.side_two.get_active_immutable().id;
+// Runs MCTS over existing trees on the rayon pool, either one task per tree or,
+// in tree-parallel mode, every thread on the first tree.
+// Setting `stop` ends the search at the next batch on every thread.
+pub fn search_trees(
+    trees: &mut [MCTS],
+    state: &State,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    config: &MctsConfig,
+    stop: &AtomicBool,
+) -> SearchDiagnostics {
+    let start_time = Instant::now();
+    let node_budget = tree_node_budget(config, trees.len());
+    for tree in trees.iter_mut() {
+        tree.set_node_budget(node_budget);
+    }
 
-                let unique_move = UniqueMove {
-                    move_choice: opp_move.clone(),
-                    pokemon_name: current_opponent_active, // Important: Store the active Pokemon at time of move
-                    is_switch: matches!(opp_move, MoveChoice::Switch(_)),
-                };
+    // The root state is the same every iteration, so its moves are generated once
+    let root_moves = ValidMoves::root(&mut state.clone(), config);
+
+    // Each thread reports (iterations, sum of leaf depths)
+    let thread_stats = match config.parallel_mode {
+        ParallelMode::Root => search_root_parallel(
+            trees,
+            state,
+            &root_moves,
+            iterations,
+            time_limit,
+            config,
+            stop,
+        ),
+        ParallelMode::Tree => search_tree_parallel(
+            &mut trees[0],
+            state,
+            &root_moves,
+            iterations,
+            time_limit,
+            config,
+            stop,
+        ),
+    };
+
+    let elapsed = start_time.elapsed();
+    let max_depth = trees
+        .iter()
+        .map(|tree| tree.max_depth_seen)
+        .max()
+        .unwrap_or(0);
+    let node_count = trees.iter().map(MCTS::node_count).sum();
+
+    SearchDiagnostics::new(
+        elapsed,
+        thread_stats.iter().map(|(visits, _)| *visits).collect(),
+        thread_stats.iter().map(|(_, depth_sum)| depth_sum).sum(),
+        max_depth,
+        node_count,
+        trees.iter().map(MCTS::memory_bytes).sum(),
+        principal_variation(trees, state),
+    )
+}
 
-                // Apply the moves and update state
-                let instructions =
-                    generate_instructions_from_move_pair(state, &our_move, &opp_move, true);
-                let chosen_inst = sample_instruction(&instructions);
-                state.apply_instructions(&chosen_inst.instruction_list);
+// Nodes each tree may hold under the search's node and byte budgets, split evenly
+// between the trees
+fn tree_node_budget(config: &MctsConfig, tree_count: usize) -> Option<usize> {
+    let from_bytes = config
+        .max_memory_bytes
+        .map(|bytes| bytes / ESTIMATED_NODE_BYTES);
+    let budget = match (config.max_nodes, from_bytes) {
+        (Some(nodes), Some(from_bytes)) => Some(nodes.min(from_bytes)),
+        (nodes, from_bytes) => nodes.or(from_bytes),
+    };
+    budget.map(|budget| (budget / tree_count.max(1)).max(1))
+}
 
-                // Record move history
-                move_history.push(MoveHistoryEntry {
-                    opp_move: opp_move,
-                    opp_active: current_opponent_active, // Use the Pokemon that was active when move was made
+fn search_root_parallel(
+    trees: &mut [MCTS],
+    state: &State,
+    root_moves: &ValidMoves,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    config: &MctsConfig,
+    stop: &AtomicBool,
+) -> Vec<(i64, i64)> {
+    let start_time = Instant::now();
+    let n_threads = trees.len();
+
+    trees
+        .par_iter_mut()
+        .enumerate()
+        .map(|(thread_index, mcts)| {
+            // A rayon task runs on one thread from start to finish, so seeding here
+            // ties the random sequence to the tree rather than the thread
+            THREAD_RNG.with(|rng| *rng.borrow_mut() = config.rng(thread_index as u64));
+            // One copy per thread, each iteration walks it down and back up
+            let mut thread_state = state.clone();
+            let root_eval = evaluate(&thread_state);
+            let start_visits = mcts.root().visits;
+            let thread_iterations =
+                iterations.map(|i| split_iterations(i, n_threads, thread_index));
+            let mut depth_sum = 0;
+
+            while !should_stop(
+                &start_time,
+                thread_iterations,
+                time_limit,
+                mcts,
+                start_visits,
+                config,
+                stop,
+            ) {
+                // Don't let the last batch overshoot the iteration budget
+                let batch_size = thread_iterations.map_or(config.batch_size, |max_iter| {
+                    let done = mcts.root().visits - start_visits;
+                    (i64::from(max_iter) - done).clamp(0, i64::from(config.batch_size)) as u32
                 });
 
-                let new_depth = current_node.lock().unwrap().depth + 1;
-                let current_our_active = state.side_one.get_active_immutable().id;
-
-                let mut new_node = MCTSNode::new(new_depth);
-                new_node.our_move = Some(our_move.clone());
-                new_node.parent = Some(Arc::downgrade(&current_node));
-                new_node.actual_opponent_move = Some(unique_move);
-                new_node.original_active = Some(current_our_active);
+                for _ in 0..batch_size {
+                    // Select and expand
+                    let (selected_node, move_history) =
+                        mcts.select_and_expand(&mut thread_state, root_moves, config);
+                    depth_sum += i64::from(mcts.node(selected_node).depth);
 
-                let new_node_arc = Arc::new(Mutex::new(new_node));
-                {
-                    let mut node_guard = current_node.lock().unwrap();
-                    node_guard
-                        .children
-                        .insert(our_move, Arc::clone(&new_node_arc));
+                    // Backpropagate the score
+                    let score = simulation_score(&thread_state, root_eval, config);
+                    unwind(&mut thread_state, &move_history);
+                    mcts.backpropagate(selected_node, score, &move_history, false);
                 }
 
-                return (new_node_arc, move_history);
+                // Make room for the next batch by dropping the least visited half
+                if config.prune && mcts.is_full() {
+                    if let Some(budget) = mcts.node_budget {
+                        mcts.prune(budget / 2);
+                    }
+                }
             }
 
-            // Selection phase
-            // if let Some(file) = debug_file.as_deref_mut() {
-            //     writeln!(file, "\nCalculating UCB scores:").unwrap();
-            // }
-
-            let selection_result = {
-                let node_guard = current_node.lock().unwrap();
-                let mut best_move = None;
-                let mut best_score = f32::NEG_INFINITY;
-                let mut best_node = None;
+            (mcts.root().visits - start_visits, depth_sum)
+        })
+        .collect()
+}
 
-                for (move_choice, child) in &node_guard.children {
-                    if valid_our_moves.contains(move_choice) {
-                        let child_guard = child.lock().unwrap();
-                        let score = child_guard.ucb1_score(node_guard.visits);
+// Every rayon thread searches the same tree. The tree is moved into a mutex for the
+// search and each iteration claims its slot up front, so the threads can't overshoot
+// the iteration budget or the visit cap between them. The order threads reach the
+// tree in isn't fixed, so a seed only makes this reproducible with one thread.
+fn search_tree_parallel(
+    tree: &mut MCTS,
+    state: &State,
+    root_moves: &ValidMoves,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    config: &MctsConfig,
+    stop: &AtomicBool,
+) -> Vec<(i64, i64)> {
+    let start_time = Instant::now();
+    let start_visits = tree.root().visits;
+    let claimed = AtomicI64::new(0);
+    let shared = Mutex::new(std::mem::replace(tree, MCTS::new()));
+    let root_eval = evaluate(state);
 
-                        if score > best_score {
-                            best_score = score;
-                            best_move = Some(move_choice.clone());
-                            best_node = Some(Arc::clone(child));
-                        }
-                    }
+    let thread_stats = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .map(|thread_index| {
+            THREAD_RNG.with(|rng| *rng.borrow_mut() = config.rng(thread_index as u64));
+            let mut thread_state = state.clone();
+            let mut thread_iterations = 0;
+            let mut depth_sum = 0;
+
+            loop {
+                if stop.load(Ordering::Relaxed)
+                    || time_limit.is_some_and(|limit| start_time.elapsed() >= limit)
+                {
+                    break;
+                }
+                let iteration = claimed.fetch_add(1, Ordering::Relaxed);
+                if iterations.is_some_and(|max_iter| iteration >= i64::from(max_iter))
+                    || start_visits + iteration >= config.max_visits
+                {
+                    break;
                 }
 
-                (best_move, best_node)
-            };
-
-            let (selected_move, next_node) = match selection_result {
-                (Some(mov), Some(node)) => (mov, node),
-                _ => return (current_node, move_history),
-            };
-
-            let selected_opp_move = if valid_opp_moves
-                .iter()
-                .all(|m| matches!(m, MoveChoice::Switch(_)))
-            {
-                valid_opp_moves[0].clone()
-            } else {
-                current_node
-                    .lock()
-                    .unwrap()
-                    .select_opponent_move(&valid_opp_moves, state)
-            };
+                let (selected_node, move_history) =
+                    select_and_expand_shared(&shared, &mut thread_state, root_moves, config);
+                let score = simulation_score(&thread_state, root_eval, config);
+                unwind(&mut thread_state, &move_history);
 
-            // Same changes in the selection phase:
-            let current_opponent_active = state.side_two.get_active_immutable().id;
+                let mut tree = shared.lock().unwrap();
+                depth_sum += i64::from(tree.node(selected_node).depth);
+                tree.backpropagate(selected_node, score, &move_history, true);
+                thread_iterations += 1;
+            }
 
-            let unique_move = UniqueMove {
-                move_choice: selected_opp_move.clone(),
-                pokemon_name: state.side_two.get_active_immutable().id,
-                is_switch: matches!(selected_opp_move, MoveChoice::Switch(_)),
-            };
+            (thread_iterations, depth_sum)
+        })
+        .collect();
 
-            let instructions = generate_instructions_from_move_pair(
-                state,
-                &selected_move,
-                &selected_opp_move,
-                true,
-            );
-            let chosen_inst = sample_instruction(&instructions);
+    *tree = shared.into_inner().unwrap();
+    thread_stats
+}
 
-            state.apply_instructions(&chosen_inst.instruction_list);
+// Follows the most visited joint move from the root of the most searched tree,
+// playing the most likely instruction branch to name the moves at each ply
+fn principal_variation(trees: &[MCTS], state: &State) -> Vec<(String, String)> {
+    let mut variation = Vec::new();
+    let Some(tree) = trees.iter().max_by_key(|tree| tree.root().visits) else {
+        return variation;
+    };
 
-            move_history.push(MoveHistoryEntry {
-                opp_move: selected_opp_move,
-                opp_active: current_opponent_active,
-            });
+    let mut pv_state = state.clone();
+    let mut current = ROOT;
 
-            // Update node with the actual opponent move
-            {
-                let mut next_node_guard = next_node.lock().unwrap();
-                next_node_guard.actual_opponent_move = Some(unique_move);
-            }
+    loop {
+        let node = tree.node(current);
+        let opp_active = pv_state.side_two.get_active_immutable().id;
 
-            current_node = next_node;
-        }
-    }
-    // Update backpropagate to handle MoveHistoryEntry
-    fn backpropagate(node: Arc<Mutex<MCTSNode>>, score: f32, move_history: &[MoveHistoryEntry]) {
-        let mut current = Arc::clone(&node);
+        let Some((our_move, next_node)) = node
+            .children
+            .iter()
+            .max_by_key(|(_, child)| tree.node(*child).visits)
+            .copied()
+        else {
+            break;
+        };
 
-        // Update the leaf node
+        let opp_move = node
+            .opponent_move_stats
+            .iter()
+            .filter(|(unique_move, _)| unique_move.pokemon_name == opp_active)
+            .max_by_key(|(_, stats)| stats.visits)
+            .map_or(MoveChoice::None, |(unique_move, _)| unique_move.move_choice);
+
+        variation.push((
+            our_move.to_string(&pv_state.side_one),
+            opp_move.to_string(&pv_state.side_two),
+        ));
+
+        let instructions =
+            generate_instructions_from_move_pair(&mut pv_state, &our_move, &opp_move, true);
+        if let Some(most_likely) = instructions
+            .iter()
+            .max_by(|a, b| a.percentage.total_cmp(&b.percentage))
         {
-            let mut node_guard = current.lock().unwrap();
-            node_guard.visits += 1;
-            node_guard.value += score;
-            node_guard.last_simulation_score = Some(score);
-        }
-
-        // Walk back up the tree
-        for entry in move_history.iter().rev() {
-            let parent = {
-                let node_guard = current.lock().unwrap();
-                node_guard.parent.as_ref().and_then(|p| p.upgrade())
-            };
-
-            if let Some(parent_node) = parent {
-                {
-                    let mut parent_guard = parent_node.lock().unwrap();
-                    parent_guard.visits += 1;
-                    parent_guard.value += score;
-
-                    // Update opponent stats using move history entry
-                    let unique_move = UniqueMove {
-                        move_choice: entry.opp_move.clone(),
-                        pokemon_name: entry.opp_active,
-                        is_switch: matches!(entry.opp_move, MoveChoice::Switch(_)),
-                    };
-
-                    let stats = parent_guard
-                        .opponent_move_stats
-                        .entry(unique_move)
-                        .or_insert(OpponentMoveStats {
-                            visits: 0,
-                            value: 0.0,
-                            last_ucb: None,
-                        });
-                    stats.visits += 1;
-                    stats.value += score;
-                }
-                current = parent_node;
-            } else {
-                break;
-            }
+            pv_state.apply_instructions(&most_likely.instruction_list);
         }
-    }
-}
 
-fn sample_instruction(instructions: &[StateInstructions]) -> &StateInstructions {
-    if instructions.len() == 1 {
-        return &instructions[0];
+        current = next_node;
     }
 
-    // Preallocate vector with known size
-    let mut weights = Vec::with_capacity(instructions.len());
-    weights.extend(instructions.iter().map(|i| i.percentage as f64));
-
-    THREAD_RNG.with(|rng| match WeightedIndex::new(&weights) {
-        Ok(dist) => &instructions[dist.sample(&mut *rng.borrow_mut())],
-        Err(_) => &instructions[0],
-    })
+    variation
 }
 
-pub fn perform_mcts_search(
-    state: &mut State,
-    iterations: Option<u32>,
-    time_limit: Option<Duration>,
-) -> (String, f32, i64) {
-    let start_time = Instant::now();
-    let n_threads = rayon::current_num_threads();
-
-    // Create parameters for each thread
-    let batch_size = 20;
-    let iterations_per_thread = iterations.map(|i| i / n_threads as u32);
-    let time_limit_ref = Arc::new(time_limit);
-
-    // Run parallel MCTS
-    let trees: Vec<_> = (0..n_threads)
-        .into_par_iter()
-        .map(|_| {
-            // Each thread gets its own MCTS tree and state
-            let thread_state = state.clone();
-            let mcts = MCTS::new();
-            let root_arc = Arc::clone(&mcts.root);
-            let max_depth_arc = Arc::clone(&mcts.max_depth_seen);
-            let time_limit = Arc::clone(&time_limit_ref);
-
-            while !should_stop(&start_time, iterations_per_thread, *time_limit, &mcts) {
-                for _ in 0..batch_size {
-                    let mut sim_state = thread_state.clone();
-                    let root_eval = evaluate(&thread_state);
-
-                    // Select and expand
-                    let (selected_node, move_history) = MCTSNode::select_and_expand(
-                        Arc::clone(&root_arc),
-                        &mut sim_state,
-                        &max_depth_arc,
-                    );
-
-                    // Compute simulation score
-                    let score = if sim_state.battle_is_over() != 0.0 {
-                        if sim_state.battle_is_over() > 0.0 {
-                            1.0
-                        } else {
-                            0.0
-                        }
-                    } else {
-                        sigmoid(evaluate(&sim_state) - root_eval)
-                    };
-
-                    // Backpropagate the score
-                    MCTSNode::backpropagate(selected_node, score, &move_history);
-                }
-            }
-            mcts
-        })
-        .collect();
-
+pub fn choose_best_move(trees: &[MCTS], state: &State, config: &MctsConfig) -> SearchResult {
     // Aggregate statistics from all trees
-    let (our_moves, _) = state.get_all_options();
+    let (our_moves, opp_moves) = state.get_all_options();
     let mut combined_stats: HashMap<MoveChoice, (i64, f32)> = HashMap::new();
-    let mut max_depth = 0;
+    let mut combined_opp_stats: HashMap<MoveChoice, (i64, f32)> = HashMap::new();
     let mut total_visits = 0;
 
-    for tree in &trees {
-        let root = tree.root.lock().unwrap();
-        max_depth = max_depth.max(*tree.max_depth_seen.lock().unwrap());
+    for tree in trees {
+        let root = tree.root();
         total_visits += root.visits;
 
         // Combine statistics
         for mov in &our_moves {
-            if let Some(child) = root.children.get(mov) {
-                let child_guard = child.lock().unwrap();
+            if let Some(child) = root.child(mov) {
+                let child = tree.node(child);
                 let entry = combined_stats.entry(mov.clone()).or_insert((0, 0.0));
-                entry.0 += child_guard.visits;
-                entry.1 += child_guard.value;
+                entry.0 += child.visits;
+                entry.1 += child.value;
             }
         }
-    }
 
-    // Choose best move based on combined statistics
-    let mut best_visits = 0;
-    let mut best_move = MoveChoice::None;
-    let mut best_score = 0.0;
-
-    for (mov, (visits, score)) in &combined_stats {
-        if *visits > best_visits {
-            best_visits = *visits;
-            best_move = *mov;
-            best_score = *score;
+        // Opponent values are stored from our side, flip them to the opponent's
+        for (unique_move, stats) in &root.opponent_move_stats {
+            let entry = combined_opp_stats
+                .entry(unique_move.move_choice)
+                .or_insert((0, 0.0));
+            entry.0 += stats.visits;
+            entry.1 += stats.visits as f32 - stats.value;
         }
     }
 
-    (
-        best_move.to_string(&state.side_one),
-        best_score / (best_visits as f32),
+    let collect_stats = |moves: &[MoveChoice], stats: &HashMap<MoveChoice, (i64, f32)>| {
+        moves
+            .iter()
+            .map(|mov| {
+                let (visits, value) = stats.get(mov).copied().unwrap_or((0, 0.0));
+                (*mov, visits, value)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut result = SearchResult::new(
+        root_move_stats(&collect_stats(&our_moves, &combined_stats), &state.side_one),
+        root_move_stats(
+            &collect_stats(&opp_moves, &combined_opp_stats),
+            &state.side_two,
+        ),
         total_visits,
-    )
+    );
+    result.choose_final_move(config);
+    result
+}
+
+// Exports the most searched tree; the trees are independent so merging them
+// below the root wouldn't describe any single search
+pub fn export_tree(result: &mut SearchResult, trees: &[MCTS], state: &State, plies: usize) {
+    if let Some(tree) = trees.iter().max_by_key(|tree| tree.root().visits) {
+        let export = tree.export(ROOT, &mut state.clone(), plies);
+        result.tree_json = Some(export.to_json());
+        result.tree_dot = Some(export.to_dot());
+    }
+}
+
+// Spread the iteration budget over the threads, handing the remainder to the first few
+fn split_iterations(iterations: u32, n_threads: usize, thread_index: usize) -> u32 {
+    let n_threads = n_threads as u32;
+    let thread_index = thread_index as u32;
+    iterations / n_threads + u32::from(thread_index < iterations % n_threads)
 }
 
 fn should_stop(
@@ -549,12 +1277,19 @@ fn should_stop(
     iterations: Option<u32>,
     time_limit: Option<Duration>,
     mcts: &MCTS,
+    start_visits: i64,
+    config: &MctsConfig,
+    stop: &AtomicBool,
 ) -> bool {
-    let visits = mcts.root.lock().unwrap().visits;
+    if stop.load(Ordering::Relaxed) {
+        return true;
+    }
+
+    let visits = mcts.root().visits;
 
-    // Check iteration limit
+    // Check iteration limit, counting only this search's visits
     if let Some(max_iter) = iterations {
-        if visits >= max_iter as i64 {
+        if visits - start_visits >= max_iter as i64 {
             return true;
         }
     }
@@ -567,5 +1302,5 @@ fn should_stop(
     }
 
     // Hard cap on total visits
-    visits >= 10_000_000
+    visits >= config.max_visits
 }
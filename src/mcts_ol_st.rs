@@ -1,26 +1,35 @@
+use crate::mcts_config::MctsConfig;
+use crate::mcts_export::ExportNode;
+use crate::mcts_result::{root_move_stats, SearchDiagnostics, SearchResult};
+use crate::observation::action_index;
+use crate::rollout::{rollout, sample_branch};
+use crate::solver::{Proof, Solver};
+use crate::state_hash::state_hash;
+use crate::valid_moves::ValidMoves;
 use poke_engine::{
     evaluate::evaluate,
     generate_instructions::generate_instructions_from_move_pair,
-    instruction::StateInstructions,
+    instruction::{Instruction, StateInstructions},
     pokemon::PokemonName,
-    state::{MoveChoice, State},
+    state::{MoveChoice, Side, State},
 };
-use rand::distributions::WeightedIndex;
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use rand_distr::Dirichlet;
 use smallvec::SmallVec;
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::{Rc, Weak};
 use std::time::{Duration, Instant};
 
-fn sigmoid(x: f32) -> f32 {
-    // Tuned so that ~200 points is very close to 1.0
-    1.0 / (1.0 + (-0.0125 * x).exp())
+fn sigmoid(x: f32, scale: f32) -> f32 {
+    1.0 / (1.0 + (-scale * x).exp())
 }
 
 // Thread-local RNG
 thread_local! {
-    static THREAD_RNG: RefCell<ThreadRng> = RefCell::new(thread_rng());
+    static THREAD_RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
 }
 
 #[derive(Clone, Hash, Eq, PartialEq)]
@@ -30,6 +39,17 @@ pub struct UniqueMove {
     is_switch: bool,
 }
 
+impl UniqueMove {
+    // Named in `side` while its Pokemon is active there, else as the engine's MoveChoice
+    fn describe(&self, side: &Side) -> String {
+        if side.get_active_immutable().id == self.pokemon_name {
+            format!("{} {}", self.pokemon_name, self.move_choice.to_string(side))
+        } else {
+            format!("{} {:?}", self.pokemon_name, self.move_choice)
+        }
+    }
+}
+
 pub struct OpponentMoveStats {
     pub visits: i64,
     pub value: f32,
@@ -40,11 +60,38 @@ pub struct OpponentMoveStats {
 pub struct MoveHistoryEntry {
     opp_move: MoveChoice,
     opp_active: PokemonName,
+    // state reached by this step, only hashed when transpositions are on
+    state_hash: Option<u64>,
+    // the branch applied in this step, reversed to walk the state back to the root
+    instructions: Vec<Instruction>,
+}
+
+impl MoveHistoryEntry {
+    fn unique_move(&self) -> UniqueMove {
+        UniqueMove {
+            move_choice: self.opp_move,
+            pokemon_name: self.opp_active,
+            is_switch: matches!(self.opp_move, MoveChoice::Switch(_)),
+        }
+    }
 }
 
 pub struct MCTS {
     root: Rc<RefCell<MCTSNode>>,
     max_depth_seen: Rc<RefCell<usize>>,
+    // scores backed up through each state hash, shared by every path that reaches it
+    transpositions: RefCell<HashMap<u64, TranspositionStats>>,
+    solver: RefCell<Solver>,
+}
+
+// Leaf evaluations made in a state, or below it, from every tree node that reached it
+#[derive(Clone, Copy, Default)]
+struct TranspositionStats {
+    visits: i64,
+    value: f32,
+    // the node that first reached the state, and whether any other node has since
+    node: usize,
+    shared: bool,
 }
 
 pub struct MCTSNode {
@@ -58,6 +105,14 @@ pub struct MCTSNode {
     pub last_simulation_score: Option<f32>,
     pub actual_opponent_move: Option<UniqueMove>,
     pub original_active: Option<PokemonName>,
+    // network priors for our moves here, set once the node has been evaluated
+    pub priors: Option<HashMap<MoveChoice, f32>>,
+    // opponent reply and instruction branch every visit here came through from the
+    // parent, None once they've differed or at the root
+    pub origin: Option<(MoveChoice, usize)>,
+    // moves of the one state an exact node is reached in, kept from its first visit.
+    // A node is exact while it and every node above it, bar the root, have an origin.
+    pub valid_moves: Option<Rc<ValidMoves>>,
 }
 
 impl MCTS {
@@ -65,6 +120,90 @@ impl MCTS {
         MCTS {
             root: Rc::new(RefCell::new(MCTSNode::new(0))),
             max_depth_seen: Rc::new(RefCell::new(0)),
+            transpositions: RefCell::new(HashMap::new()),
+            solver: RefCell::new(Solver::default()),
+        }
+    }
+
+    // Rough heap memory of the tree, the transposition table and the solver's maps
+    fn memory_bytes(&self) -> usize {
+        MCTSNode::memory_bytes(&self.root)
+            + self.transpositions.borrow().capacity() * (size_of::<(u64, TranspositionStats)>() + 1)
+            + self.solver.borrow().memory_bytes()
+    }
+
+    // Walks `state` back up to the root. Given the hash of a proven leaf state, it
+    // re-solves each state on the way until one can't be proven yet, or has already
+    // been solved with the proven state below it.
+    fn unwind(
+        &self,
+        state: &mut State,
+        move_history: &[MoveHistoryEntry],
+        proven_leaf: Option<u64>,
+    ) {
+        let mut solver = self.solver.borrow_mut();
+        let mut proven_child = proven_leaf;
+        for entry in move_history.iter().rev() {
+            state.reverse_instructions(&entry.instructions);
+            proven_child = proven_child.and_then(|child| {
+                let hash = state_hash(state);
+                (solver.first_propagation(hash, child) && solver.solve(state).is_some())
+                    .then_some(hash)
+            });
+        }
+    }
+
+    // Blends a leaf's fresh evaluation with every earlier one made in or below its state,
+    // once another tree node has reached that state too. Evaluations made only through
+    // this node are already in its own statistics.
+    fn transposition_score(
+        &self,
+        leaf: &Rc<RefCell<MCTSNode>>,
+        move_history: &[MoveHistoryEntry],
+        evaluation: f32,
+    ) -> f32 {
+        let Some(hash) = move_history.last().and_then(|entry| entry.state_hash) else {
+            return evaluation;
+        };
+        match self.transpositions.borrow().get(&hash) {
+            Some(stats) if stats.shared || stats.node != Rc::as_ptr(leaf) as usize => {
+                (stats.value + evaluation) / (stats.visits + 1) as f32
+            }
+            _ => evaluation,
+        }
+    }
+
+    // Adds the leaf's evaluation to the state of every node on its path
+    fn record_transpositions(
+        &self,
+        leaf: &Rc<RefCell<MCTSNode>>,
+        move_history: &[MoveHistoryEntry],
+        evaluation: f32,
+    ) {
+        if move_history
+            .first()
+            .is_none_or(|entry| entry.state_hash.is_none())
+        {
+            return;
+        }
+
+        let mut transpositions = self.transpositions.borrow_mut();
+        let mut node = Some(Rc::clone(leaf));
+        for entry in move_history.iter().rev() {
+            let Some(current) = node else {
+                break;
+            };
+            if let Some(hash) = entry.state_hash {
+                let id = Rc::as_ptr(&current) as usize;
+                let stats = transpositions.entry(hash).or_insert(TranspositionStats {
+                    node: id,
+                    ..Default::default()
+                });
+                stats.shared |= stats.node != id;
+                stats.visits += 1;
+                stats.value += evaluation;
+            }
+            node = current.borrow().parent.as_ref().and_then(Weak::upgrade);
         }
     }
 }
@@ -82,17 +221,142 @@ impl MCTSNode {
             last_simulation_score: None,
             actual_opponent_move: None,
             original_active: None,
+            priors: None,
+            origin: None,
+            valid_moves: None,
         }
     }
 
+    fn count_nodes(node: &Rc<RefCell<MCTSNode>>) -> usize {
+        let node_ref = node.borrow();
+        1 + node_ref
+            .children
+            .values()
+            .map(MCTSNode::count_nodes)
+            .sum::<usize>()
+    }
+
+    // Rough heap memory of the subtree: each node's shared allocation plus its maps'
+    // buckets, one control byte per bucket
+    fn memory_bytes(node: &Rc<RefCell<MCTSNode>>) -> usize {
+        let node_ref = node.borrow();
+        size_of::<RefCell<MCTSNode>>()
+            + 2 * size_of::<usize>()
+            + node_ref.children.capacity() * (size_of::<(MoveChoice, Rc<RefCell<MCTSNode>>)>() + 1)
+            + node_ref.opponent_move_stats.capacity()
+                * (size_of::<(UniqueMove, OpponentMoveStats)>() + 1)
+            + node_ref.priors.as_ref().map_or(0, |priors| {
+                priors.capacity() * (size_of::<(MoveChoice, f32)>() + 1)
+            })
+            + node_ref
+                .valid_moves
+                .as_ref()
+                .map_or(0, |moves| moves.memory_bytes())
+            + node_ref
+                .children
+                .values()
+                .map(MCTSNode::memory_bytes)
+                .sum::<usize>()
+    }
+
+    // Snapshot of this node, reached in `state`, and `plies` levels below it, children
+    // sorted by visits. Moves are named in the state they were chosen in, so the
+    // node's own are left for its parent to fill in.
+    fn export(node: &Rc<RefCell<MCTSNode>>, state: &mut State, plies: usize) -> ExportNode {
+        let node_ref = node.borrow();
+
+        let mut children: Vec<_> = if plies > 0 {
+            node_ref
+                .children
+                .iter()
+                .map(|(our_move, child)| MCTSNode::export_child(*our_move, child, state, plies - 1))
+                .collect()
+        } else {
+            vec![]
+        };
+        children.sort_by_key(|child| std::cmp::Reverse(child.visits));
+
+        let mut opponent_ucb: Vec<_> = node_ref
+            .opponent_move_stats
+            .iter()
+            .map(|(unique_move, stats)| {
+                (
+                    unique_move.describe(&state.side_two),
+                    stats.visits,
+                    stats.last_ucb,
+                )
+            })
+            .collect();
+        opponent_ucb.sort_by_key(|(_, visits, _)| std::cmp::Reverse(*visits));
+
+        ExportNode {
+            our_move: None,
+            actual_opponent_move: None,
+            original_active: node_ref.original_active.map(|p| p.to_string()),
+            visits: node_ref.visits,
+            value: node_ref.value / node_ref.visits.max(1) as f32,
+            last_simulation_score: node_ref.last_simulation_score,
+            opponent_ucb,
+            children,
+        }
+    }
+
+    // Exports a child of the node reached in `state`, from the state one joint move on:
+    // the reply and branch every visit came through, or else the reply it was expanded
+    // with and its most likely branch, as in the principal variation
+    fn export_child(
+        our_move: MoveChoice,
+        child: &Rc<RefCell<MCTSNode>>,
+        state: &mut State,
+        plies: usize,
+    ) -> ExportNode {
+        let (opp_move, branch, opp_label) = {
+            let child_ref = child.borrow();
+            let opp_active = state.side_two.get_active_immutable().id;
+            let (opp_move, branch) = match (child_ref.origin, &child_ref.actual_opponent_move) {
+                (Some((opp_move, branch)), _) => (opp_move, Some(branch)),
+                (None, Some(reply)) if reply.pokemon_name == opp_active => {
+                    (reply.move_choice, None)
+                }
+                (None, _) => (MoveChoice::None, None),
+            };
+            let opp_label = child_ref
+                .actual_opponent_move
+                .as_ref()
+                .map(|reply| reply.describe(&state.side_two));
+            (opp_move, branch, opp_label)
+        };
+        let our_label = our_move.to_string(&state.side_one);
+
+        let instructions = generate_instructions_from_move_pair(state, &our_move, &opp_move, true);
+        let chosen = branch.and_then(|b| instructions.get(b)).or_else(|| {
+            instructions
+                .iter()
+                .max_by(|a, b| a.percentage.total_cmp(&b.percentage))
+        });
+
+        let mut export = match chosen {
+            Some(chosen) => {
+                state.apply_instructions(&chosen.instruction_list);
+                let export = MCTSNode::export(child, state, plies);
+                state.reverse_instructions(&chosen.instruction_list);
+                export
+            }
+            None => MCTSNode::export(child, state, plies),
+        };
+        export.our_move = Some(our_label);
+        export.actual_opponent_move = opp_label;
+        export
+    }
+
     #[inline(always)]
-    pub fn ucb1_score(&self, parent_visits: i64) -> f32 {
+    pub fn ucb1_score(&self, parent_visits: i64, exploration_constant: f32) -> f32 {
         if self.visits == 0 {
             return f32::INFINITY;
         }
         let visits_f = self.visits as f32;
         let exploit = self.value / visits_f;
-        let explore = (2.0 * (parent_visits as f32).ln() / visits_f).sqrt();
+        let explore = (exploration_constant * (parent_visits as f32).ln() / visits_f).sqrt();
         exploit + explore
     }
 
@@ -100,6 +364,7 @@ impl MCTSNode {
         &mut self,
         available_moves: &[MoveChoice],
         state: &State,
+        exploration_constant: f32,
     ) -> MoveChoice {
         let current_opponent_active = state.side_two.get_active_immutable().id;
 
@@ -117,7 +382,8 @@ impl MCTSNode {
             .collect();
 
         if !untried_moves.is_empty() {
-            let chosen = untried_moves[thread_rng().gen_range(0..untried_moves.len())].clone();
+            let chosen = THREAD_RNG
+                .with(|rng| *untried_moves[rng.borrow_mut().gen_range(0..untried_moves.len())]);
             let unique_move = UniqueMove {
                 move_choice: chosen.clone(),
                 pokemon_name: current_opponent_active,
@@ -155,7 +421,9 @@ impl MCTSNode {
 
             if let Some(stats) = self.opponent_move_stats.get_mut(&unique_move) {
                 let exploitation = 1.0 - (stats.value / stats.visits as f32);
-                let exploration = (2.0 * (total_visits as f32).ln() / stats.visits as f32).sqrt();
+                let exploration = (exploration_constant * (total_visits as f32).ln()
+                    / stats.visits as f32)
+                    .sqrt();
                 let ucb_score = exploitation + exploration;
                 stats.last_ucb = Some(ucb_score);
 
@@ -169,13 +437,62 @@ impl MCTSNode {
         best_move.unwrap_or_else(|| available_moves[0].clone())
     }
 
+    // PUCT over our moves, tried or not. Moves get a uniform prior until the node has
+    // been evaluated and unvisited ones start from the node's own mean value.
+    fn puct_move(&self, moves: &[MoveChoice], puct_constant: f32) -> MoveChoice {
+        let node_value = if self.visits > 0 {
+            self.value / self.visits as f32
+        } else {
+            0.5
+        };
+        let sqrt_visits = (self.visits as f32).sqrt();
+        let uniform_prior = 1.0 / moves.len() as f32;
+
+        let mut best_score = f32::NEG_INFINITY;
+        let mut best_move = moves[0];
+
+        for m in moves {
+            let prior = self
+                .priors
+                .as_ref()
+                .and_then(|priors| priors.get(m).copied())
+                .unwrap_or(uniform_prior);
+            let (visits, value) = self.children.get(m).map_or((0, node_value), |child| {
+                let child = child.borrow();
+                if child.visits > 0 {
+                    (child.visits, child.value / child.visits as f32)
+                } else {
+                    (0, node_value)
+                }
+            });
+
+            let score = value + puct_constant * prior * sqrt_visits / (1 + visits) as f32;
+            if score > best_score {
+                best_score = score;
+                best_move = *m;
+            }
+        }
+
+        best_move
+    }
+
+    // With `puct`, our moves are chosen by PUCT instead of trying each once then UCB1.
+    // With a solver, proven states end the selection like terminal ones and moves
+    // that lose outright are skipped while there's anything else to try.
+    // `root_moves` are the valid moves of the root state, generated once per search.
     fn select_and_expand(
         node: Rc<RefCell<MCTSNode>>,
         state: &mut State,
+        root_moves: &ValidMoves,
         max_depth_seen: &Rc<RefCell<usize>>,
+        config: &MctsConfig,
+        puct: bool,
+        solver: Option<&Solver>,
     ) -> (Rc<RefCell<MCTSNode>>, SmallVec<[MoveHistoryEntry; 16]>) {
         let mut current_node = node;
         let mut move_history = SmallVec::new();
+        // whether every node so far has only been reached through this line
+        let mut exact = true;
 
         loop {
             {
@@ -184,49 +501,58 @@ impl MCTSNode {
                 *depth_seen = (*depth_seen).max(node_guard.depth as usize);
             }
 
-            let (our_moves, opp_moves) = state.get_all_options();
-            if state.battle_is_over() != 0.0 || (our_moves.is_empty() && opp_moves.is_empty()) {
-                return (current_node, move_history);
-            }
-
-            let valid_our_moves = if our_moves.contains(&MoveChoice::None) {
-                vec![MoveChoice::None]
+            let cached_moves;
+            let fresh_moves;
+            let valid_moves: &ValidMoves = if move_history.is_empty() {
+                root_moves
+            } else if exact {
+                cached_moves = Rc::clone(
+                    current_node
+                        .borrow_mut()
+                        .valid_moves
+                        .get_or_insert_with(|| Rc::new(ValidMoves::new(state))),
+                );
+                &cached_moves
             } else {
-                our_moves
-                    .iter()
-                    .filter(|&m| !matches!(m, MoveChoice::None))
-                    .cloned()
-                    .collect::<Vec<_>>()
+                // a node reached through a second line never is exact again
+                current_node.borrow_mut().valid_moves = None;
+                fresh_moves = ValidMoves::new(state);
+                &fresh_moves
             };
+            if valid_moves.is_terminal() {
+                return (current_node, move_history);
+            }
 
-            let valid_opp_moves = if opp_moves.contains(&MoveChoice::None) {
-                let switch_moves: Vec<_> = opp_moves
-                    .iter()
-                    .filter(|&m| matches!(m, MoveChoice::Switch(_)))
-                    .cloned()
-                    .collect();
-                if !switch_moves.is_empty() {
-                    switch_moves
-                } else {
-                    vec![MoveChoice::None]
+            let mut valid_our_moves = Cow::Borrowed(valid_moves.ours.as_slice());
+            if let Some(solver) = solver {
+                let hash = state_hash(state);
+                if solver.proof(hash).is_some() {
+                    return (current_node, move_history);
                 }
-            } else if our_moves.iter().all(|m| matches!(m, MoveChoice::Switch(_))) {
-                vec![MoveChoice::None]
-            } else {
-                opp_moves
-                    .iter()
-                    .filter(|&m| !matches!(m, MoveChoice::None))
-                    .cloned()
-                    .collect::<Vec<_>>()
-            };
+                let lost_moves = solver.lost_moves(hash);
+                if valid_our_moves.iter().any(|m| !lost_moves.contains(m)) {
+                    valid_our_moves.to_mut().retain(|m| !lost_moves.contains(m));
+                }
+            }
+
+            let valid_opp_moves = &valid_moves.theirs;
+
+            let puct_move = puct.then(|| {
+                current_node
+                    .borrow()
+                    .puct_move(&valid_our_moves, config.puct_constant)
+            });
 
             // Check for untried moves
             let untried_move = {
                 let node_guard = current_node.borrow();
-                valid_our_moves
-                    .iter()
-                    .find(|m| !node_guard.children.contains_key(*m))
-                    .cloned()
+                match puct_move {
+                    Some(m) => (!node_guard.children.contains_key(&m)).then_some(m),
+                    None => valid_our_moves
+                        .iter()
+                        .find(|m| !node_guard.children.contains_key(*m))
+                        .cloned(),
+                }
             };
 
             if let Some(our_move) = untried_move {
@@ -239,9 +565,11 @@ impl MCTSNode {
                 {
                     valid_opp_moves[0].clone()
                 } else {
-                    current_node
-                        .borrow_mut()
-                        .select_opponent_move(&valid_opp_moves, state)
+                    current_node.borrow_mut().select_opponent_move(
+                        valid_opp_moves,
+                        state,
+                        config.opponent_exploration_constant,
+                    )
                 };
 
                 let unique_move = UniqueMove {
@@ -250,14 +578,16 @@ impl MCTSNode {
                     is_switch: matches!(opp_move, MoveChoice::Switch(_)),
                 };
 
-                let instructions =
-                    generate_instructions_from_move_pair(state, &our_move, &opp_move, true);
-                let chosen_inst = sample_instruction(&instructions);
-                state.apply_instructions(&chosen_inst.instruction_list);
+                let (branch, instructions) = sample_instruction(
+                    generate_instructions_from_move_pair(state, &our_move, &opp_move, true),
+                );
+                state.apply_instructions(&instructions);
 
                 move_history.push(MoveHistoryEntry {
                     opp_move: opp_move.clone(),
                     opp_active: current_opponent_active,
+                    state_hash: config.transpositions.then(|| state_hash(state)),
+                    instructions,
                 });
 
                 let new_depth = current_node.borrow().depth + 1;
@@ -266,6 +596,7 @@ impl MCTSNode {
                 new_node.parent = Some(Rc::downgrade(&current_node));
                 new_node.actual_opponent_move = Some(unique_move);
                 new_node.original_active = Some(current_our_active);
+                new_node.origin = Some((opp_move, branch));
 
                 let new_node_rc = Rc::new(RefCell::new(new_node));
                 current_node
@@ -277,16 +608,21 @@ impl MCTSNode {
             }
 
             // Selection phase
-            let selection_result = {
+            let selection_result = if let Some(m) = puct_move {
+                (Some(m), current_node.borrow().children.get(&m).cloned())
+            } else {
                 let node_guard = current_node.borrow();
                 let mut best_move = None;
                 let mut best_score = f32::NEG_INFINITY;
                 let mut best_node = None;
 
-                for (move_choice, child) in &node_guard.children {
-                    if valid_our_moves.contains(move_choice) {
+                // Walk the moves in order so ties break the same way every run
+                for move_choice in valid_our_moves.iter() {
+                    if let Some(child) = node_guard.children.get(move_choice) {
                         let child_guard = child.borrow();
-                        let score = child_guard.ucb1_score(node_guard.visits);
+                        let score = child_guard
+                            .ucb1_score(node_guard.visits, config.exploration_constant)
+                            + valid_moves.bias(move_choice, child_guard.visits, config);
 
                         if score > best_score {
                             best_score = score;
@@ -309,9 +645,11 @@ impl MCTSNode {
             {
                 valid_opp_moves[0].clone()
             } else {
-                current_node
-                    .borrow_mut()
-                    .select_opponent_move(&valid_opp_moves, state)
+                current_node.borrow_mut().select_opponent_move(
+                    valid_opp_moves,
+                    state,
+                    config.opponent_exploration_constant,
+                )
             };
 
             let current_opponent_active = state.side_two.get_active_immutable().id;
@@ -321,31 +659,78 @@ impl MCTSNode {
                 is_switch: matches!(selected_opp_move, MoveChoice::Switch(_)),
             };
 
-            let instructions = generate_instructions_from_move_pair(
+            let (branch, instructions) = sample_instruction(generate_instructions_from_move_pair(
                 state,
                 &selected_move,
                 &selected_opp_move,
                 true,
-            );
-            let chosen_inst = sample_instruction(&instructions);
-            state.apply_instructions(&chosen_inst.instruction_list);
+            ));
+            state.apply_instructions(&instructions);
 
             move_history.push(MoveHistoryEntry {
                 opp_move: selected_opp_move,
                 opp_active: current_opponent_active,
+                state_hash: config.transpositions.then(|| state_hash(state)),
+                instructions,
             });
 
-            next_node.borrow_mut().actual_opponent_move = Some(unique_move);
+            {
+                let mut next = next_node.borrow_mut();
+                next.actual_opponent_move = Some(unique_move);
+                if next.origin != Some((selected_opp_move, branch)) {
+                    next.origin = None;
+                }
+                exact = exact && next.origin.is_some();
+            }
             current_node = next_node;
         }
     }
-    fn backpropagate(node: Rc<RefCell<MCTSNode>>, score: f32, move_history: &[MoveHistoryEntry]) {
+    // Counts a pending iteration as a loss for whoever chose each move on its path, so
+    // the rest of a batch spreads out instead of following the same line
+    fn add_virtual_loss(node: &Rc<RefCell<MCTSNode>>, move_history: &[MoveHistoryEntry]) {
+        let mut current = Rc::clone(node);
+        current.borrow_mut().visits += 1;
+
+        for entry in move_history.iter().rev() {
+            let parent = current.borrow().parent.as_ref().and_then(|p| p.upgrade());
+            let Some(parent_node) = parent else {
+                break;
+            };
+
+            {
+                let mut parent_guard = parent_node.borrow_mut();
+                parent_guard.visits += 1;
+                let stats = parent_guard
+                    .opponent_move_stats
+                    .entry(entry.unique_move())
+                    .or_insert(OpponentMoveStats {
+                        visits: 0,
+                        value: 0.0,
+                        last_ucb: None,
+                    });
+                stats.visits += 1;
+                stats.value += 1.0;
+            }
+            current = parent_node;
+        }
+    }
+
+    // With `virtual_loss`, visits were already counted by add_virtual_loss and the
+    // opponent's virtual loss is taken back
+    fn backpropagate(
+        node: Rc<RefCell<MCTSNode>>,
+        score: f32,
+        move_history: &[MoveHistoryEntry],
+        virtual_loss: bool,
+    ) {
+        let visit = i64::from(!virtual_loss);
+        let opponent_correction = if virtual_loss { 1.0 } else { 0.0 };
         let mut current = node;
 
         // Update the leaf node
         {
             let mut node_guard = current.borrow_mut();
-            node_guard.visits += 1;
+            node_guard.visits += visit;
             node_guard.value += score;
             node_guard.last_simulation_score = Some(score);
         }
@@ -360,25 +745,19 @@ impl MCTSNode {
             if let Some(parent_node) = parent {
                 {
                     let mut parent_guard = parent_node.borrow_mut();
-                    parent_guard.visits += 1;
+                    parent_guard.visits += visit;
                     parent_guard.value += score;
 
-                    let unique_move = UniqueMove {
-                        move_choice: entry.opp_move.clone(),
-                        pokemon_name: entry.opp_active,
-                        is_switch: matches!(entry.opp_move, MoveChoice::Switch(_)),
-                    };
-
                     let stats = parent_guard
                         .opponent_move_stats
-                        .entry(unique_move)
+                        .entry(entry.unique_move())
                         .or_insert(OpponentMoveStats {
                             visits: 0,
                             value: 0.0,
                             last_ucb: None,
                         });
-                    stats.visits += 1;
-                    stats.value += score;
+                    stats.visits += visit;
+                    stats.value += score - opponent_correction;
                 }
                 current = parent_node;
             } else {
@@ -388,79 +767,444 @@ impl MCTSNode {
     }
 }
 
-fn sample_instruction(instructions: &[StateInstructions]) -> &StateInstructions {
-    if instructions.len() == 1 {
-        return &instructions[0];
-    }
-
-    let mut weights = Vec::with_capacity(instructions.len());
-    weights.extend(instructions.iter().map(|i| i.percentage as f64));
-
-    THREAD_RNG.with(|rng| match WeightedIndex::new(&weights) {
-        Ok(dist) => &instructions[dist.sample(&mut *rng.borrow_mut())],
-        Err(_) => &instructions[0],
-    })
+// Picks one branch by its probability and takes its instructions, along with its index
+fn sample_instruction(mut instructions: Vec<StateInstructions>) -> (usize, Vec<Instruction>) {
+    let index = THREAD_RNG.with(|rng| sample_branch(&instructions, &mut rng.borrow_mut()));
+    (index, instructions.swap_remove(index).instruction_list)
 }
 
 pub fn perform_mcts_search_st(
     state: &mut State,
     iterations: Option<u32>,
     time_limit: Option<Duration>,
-) -> (Vec<(String, f32)>, i64) {
+    config: &MctsConfig,
+    export_depth: Option<usize>,
+) -> SearchResult {
     let start_time = Instant::now();
     let mcts = MCTS::new();
     let root_eval = evaluate(state);
+    THREAD_RNG.with(|rng| *rng.borrow_mut() = config.rng(0));
+
+    let mut depth_sum = 0;
+    let root_moves = ValidMoves::root(state, config);
+    let root_hash = config.solver.then(|| state_hash(state));
+    let root_proof = || root_hash.and_then(|hash| mcts.solver.borrow().proof(hash));
+
+    // A proven root needs no more searching
+    while !should_stop(&start_time, iterations, time_limit, &mcts, config) && root_proof().is_none()
+    {
+        // Each iteration walks `state` down the tree and unwinds it back to the root
+        let (selected_node, move_history) = {
+            let solver = mcts.solver.borrow();
+            MCTSNode::select_and_expand(
+                Rc::clone(&mcts.root),
+                state,
+                &root_moves,
+                &mcts.max_depth_seen,
+                config,
+                false,
+                config.solver.then_some(&*solver),
+            )
+        };
+        depth_sum += i64::from(selected_node.borrow().depth);
 
-    while !should_stop(&start_time, iterations, time_limit, &mcts) {
-        let mut sim_state = state.clone();
-        let (selected_node, move_history) = MCTSNode::select_and_expand(
-            Rc::clone(&mcts.root),
-            &mut sim_state,
-            &mcts.max_depth_seen,
-        );
-
-        let score = if sim_state.battle_is_over() != 0.0 {
-            if sim_state.battle_is_over() > 0.0 {
-                1.0
-            } else {
-                0.0
-            }
+        let proven = if config.solver {
+            mcts.solver.borrow().proven_value(state)
         } else {
-            sigmoid(evaluate(&sim_state) - root_eval)
+            None
+        };
+        let evaluation = proven.unwrap_or_else(|| {
+            let rolled_out = THREAD_RNG.with(|rng| rollout(state, config, &mut rng.borrow_mut()));
+            let leaf_state = rolled_out.as_ref().unwrap_or(state);
+            terminal_score(leaf_state)
+                .unwrap_or_else(|| sigmoid(evaluate(leaf_state) - root_eval, config.sigmoid_scale))
+        });
+        // A state also reached through another move order shares its evaluations
+        let score = match proven {
+            Some(value) => value,
+            None => mcts.transposition_score(&selected_node, &move_history, evaluation),
         };
 
-        MCTSNode::backpropagate(selected_node, score, &move_history);
+        let proven_leaf = proven.map(|_| {
+            move_history
+                .last()
+                .and_then(|entry| entry.state_hash)
+                .unwrap_or_else(|| state_hash(state))
+        });
+        mcts.record_transpositions(&selected_node, &move_history, evaluation);
+        MCTSNode::backpropagate(selected_node, score, &move_history, false);
+        mcts.unwind(state, &move_history, proven_leaf);
+    }
+
+    let mut result = search_result(
+        &mcts,
+        state,
+        config,
+        start_time.elapsed(),
+        depth_sum,
+        export_depth,
+    );
+    match root_proof() {
+        Some(Proof::Win(winning_move)) => {
+            result.best_move = winning_move.to_string(&state.side_one);
+            result.score = 1.0;
+        }
+        Some(Proof::Loss) => result.score = 0.0,
+        None => {}
+    }
+    result
+}
+
+// Side one's exact result once the battle is over
+fn terminal_score(state: &State) -> Option<f32> {
+    let result = state.battle_is_over();
+    if result == 0.0 {
+        None
+    } else if result > 0.0 {
+        Some(1.0)
+    } else {
+        Some(0.0)
     }
+}
+
+fn search_result(
+    mcts: &MCTS,
+    state: &State,
+    config: &MctsConfig,
+    elapsed: Duration,
+    depth_sum: i64,
+    export_depth: Option<usize>,
+) -> SearchResult {
     let root = mcts.root.borrow();
 
-    choose_best_move(&root, state)
+    let mut result = choose_best_move(&root, state, config);
+    result.diagnostics = SearchDiagnostics::new(
+        elapsed,
+        vec![root.visits],
+        depth_sum,
+        *mcts.max_depth_seen.borrow(),
+        MCTSNode::count_nodes(&mcts.root),
+        mcts.memory_bytes(),
+        principal_variation(&mcts.root, state),
+    );
+
+    if let Some(plies) = export_depth {
+        let tree = MCTSNode::export(&mcts.root, &mut state.clone(), plies);
+        result.tree_json = Some(tree.to_json());
+        result.tree_dot = Some(tree.to_dot());
+    }
+    result
 }
 
-fn choose_best_move(root: &MCTSNode, state: &State) -> (Vec<(String, f32)>, i64) {
-    let (our_moves, _) = state.get_all_options();
-    let mut combined_stats: HashMap<MoveChoice, (i64, f32)> = HashMap::new();
+// A network's output for one leaf: priors over the ACTION_SPACE slots for side one's
+// moves and side one's chance of winning in [0, 1]
+pub struct LeafEvaluation {
+    pub priors: Vec<f32>,
+    pub value: f32,
+}
 
-    // Collect statistics
-    for mov in &our_moves {
-        if let Some(child) = root.children.get(mov) {
-            let child_ref = child.borrow();
-            let entry = combined_stats.entry(mov.clone()).or_insert((0, 0.0));
-            entry.0 += child_ref.visits;
-            entry.1 += child_ref.value;
+struct PendingLeaf {
+    node: Rc<RefCell<MCTSNode>>,
+    move_history: SmallVec<[MoveHistoryEntry; 16]>,
+    state: State,
+}
+
+// A PUCT search whose leaves are evaluated outside the tree, a batch at a time.
+// Leaves waiting on an evaluation hold a virtual loss so a batch spreads out.
+pub struct PuctSearch {
+    mcts: MCTS,
+    state: State,
+    config: MctsConfig,
+    pending: Vec<PendingLeaf>,
+    depth_sum: i64,
+    start_time: Instant,
+    // this search's own random stream, many searches can be stepped on one thread
+    rng: StdRng,
+    root_moves: ValidMoves,
+}
+
+impl PuctSearch {
+    pub fn new(mut state: State, config: MctsConfig) -> Self {
+        PuctSearch {
+            mcts: MCTS::new(),
+            rng: config.rng(0),
+            root_moves: ValidMoves::root(&mut state, &config),
+            state,
+            config,
+            pending: Vec::new(),
+            depth_sum: 0,
+            start_time: Instant::now(),
+        }
+    }
+
+    pub fn visits(&self) -> i64 {
+        self.mcts.root.borrow().visits
+    }
+
+    // Selects up to `count` more leaves to evaluate. Leaves where the battle is over
+    // are backed up straight away with the exact result instead. The root is
+    // evaluated on its own first so the first batch already has priors to follow.
+    pub fn select_leaves(&mut self, count: usize) {
+        // Selection draws from the thread-local RNG, so this search's stream is swapped
+        // in for the duration and other searches on the thread leave it alone
+        THREAD_RNG.with(|rng| std::mem::swap(&mut *rng.borrow_mut(), &mut self.rng));
+        self.select(count);
+        THREAD_RNG.with(|rng| std::mem::swap(&mut *rng.borrow_mut(), &mut self.rng));
+    }
+
+    fn select(&mut self, count: usize) {
+        let root_evaluated = self.mcts.root.borrow().priors.is_some();
+        if !root_evaluated && terminal_score(&self.state).is_none() {
+            if self.pending.is_empty() {
+                self.queue_leaf(
+                    Rc::clone(&self.mcts.root),
+                    SmallVec::new(),
+                    self.state.clone(),
+                );
+            }
+            return;
+        }
+
+        for _ in 0..count {
+            if self.visits() >= self.config.max_visits {
+                break;
+            }
+
+            let (node, move_history) = MCTSNode::select_and_expand(
+                Rc::clone(&self.mcts.root),
+                &mut self.state,
+                &self.root_moves,
+                &self.mcts.max_depth_seen,
+                &self.config,
+                true,
+                None,
+            );
+
+            // Only leaves waiting on the evaluator keep a copy of their state
+            if let Some(score) = terminal_score(&self.state) {
+                self.mcts.unwind(&mut self.state, &move_history, None);
+                self.depth_sum += i64::from(node.borrow().depth);
+                MCTSNode::backpropagate(node, score, &move_history, false);
+            } else {
+                let leaf_state = self.state.clone();
+                self.mcts.unwind(&mut self.state, &move_history, None);
+                self.queue_leaf(node, move_history, leaf_state);
+            }
+        }
+    }
+
+    fn queue_leaf(
+        &mut self,
+        node: Rc<RefCell<MCTSNode>>,
+        move_history: SmallVec<[MoveHistoryEntry; 16]>,
+        state: State,
+    ) {
+        MCTSNode::add_virtual_loss(&node, &move_history);
+        self.pending.push(PendingLeaf {
+            node,
+            move_history,
+            state,
+        });
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    // States of the leaves waiting on an evaluation, in the order `submit` expects
+    pub fn pending_states(&self) -> Vec<&State> {
+        self.pending.iter().map(|leaf| &leaf.state).collect()
+    }
+
+    // Backs up one evaluation per pending leaf. Priors are masked to the legal moves
+    // at each leaf and renormalised, falling back to uniform if none are left.
+    pub fn submit(&mut self, evaluations: &[LeafEvaluation]) {
+        for (leaf, evaluation) in self.pending.drain(..).zip(evaluations) {
+            let moves = ValidMoves::new(&leaf.state).ours;
+
+            let mut priors: HashMap<MoveChoice, f32> = moves
+                .iter()
+                .map(|m| {
+                    let prior = action_index(m)
+                        .and_then(|i| evaluation.priors.get(i))
+                        .map_or(0.0, |p| p.max(0.0));
+                    (*m, prior)
+                })
+                .collect();
+            let total = priors.values().sum::<f32>();
+            for prior in priors.values_mut() {
+                *prior = if total > 0.0 {
+                    *prior / total
+                } else {
+                    1.0 / moves.len() as f32
+                };
+            }
+            if leaf.move_history.is_empty() {
+                add_root_noise(&moves, &mut priors, &self.config, &mut self.rng);
+            }
+
+            self.depth_sum += i64::from(leaf.node.borrow().depth);
+            leaf.node.borrow_mut().priors = Some(priors);
+            MCTSNode::backpropagate(
+                leaf.node,
+                evaluation.value.clamp(0.0, 1.0),
+                &leaf.move_history,
+                true,
+            );
+        }
+    }
+
+    pub fn result(&self, export_depth: Option<usize>) -> SearchResult {
+        search_result(
+            &self.mcts,
+            &self.state,
+            &self.config,
+            self.start_time.elapsed(),
+            self.depth_sum,
+            export_depth,
+        )
+    }
+}
+
+// Mixes Dirichlet noise into the root priors so self-play keeps trying moves the
+// network dislikes
+fn add_root_noise(
+    moves: &[MoveChoice],
+    priors: &mut HashMap<MoveChoice, f32>,
+    config: &MctsConfig,
+    rng: &mut StdRng,
+) {
+    let epsilon = config.dirichlet_epsilon;
+    if epsilon <= 0.0 || moves.len() < 2 {
+        return;
+    }
+    let Ok(dirichlet) = Dirichlet::new_with_size(config.dirichlet_alpha, moves.len()) else {
+        return;
+    };
+
+    let noise = dirichlet.sample(rng);
+    for (m, n) in moves.iter().zip(noise) {
+        if let Some(prior) = priors.get_mut(m) {
+            *prior = (1.0 - epsilon) * *prior + epsilon * n;
         }
     }
+}
+
+// Runs a PUCT search, handing `evaluate` up to `batch_size` leaf states at a time
+pub fn perform_puct_search<E>(
+    state: &State,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    config: &MctsConfig,
+    export_depth: Option<usize>,
+    mut evaluate: impl FnMut(&[&State]) -> Result<Vec<LeafEvaluation>, E>,
+) -> Result<SearchResult, E> {
+    let start_time = Instant::now();
+    let mut search = PuctSearch::new(state.clone(), config.clone());
+
+    while !should_stop(&start_time, iterations, time_limit, &search.mcts, config) {
+        let remaining = iterations.map_or(config.batch_size, |i| {
+            (i64::from(i) - search.visits()).clamp(1, i64::from(config.batch_size)) as u32
+        });
+        search.select_leaves(remaining as usize);
+
+        if !search.pending.is_empty() {
+            let evaluations = evaluate(&search.pending_states())?;
+            search.submit(&evaluations);
+        }
+    }
+
+    Ok(search.result(export_depth))
+}
+
+// Follows the most visited joint move from the root, playing the most likely
+// instruction branch to name the moves at each ply
+fn principal_variation(root: &Rc<RefCell<MCTSNode>>, state: &State) -> Vec<(String, String)> {
+    let mut variation = Vec::new();
+    let mut pv_state = state.clone();
+    let mut current = Rc::clone(root);
 
-    // Choose best move based on visit count
-    let mut policy = Vec::new();
+    loop {
+        let (our_move, next_node, opp_move) = {
+            let node_ref = current.borrow();
+            let opp_active = pv_state.side_two.get_active_immutable().id;
 
-    for (mov, (visits, score)) in &combined_stats {
-        policy.push((
-            mov.to_string(&state.side_one),
-            *visits as f32 / root.visits as f32,
+            let best_child = node_ref
+                .children
+                .iter()
+                .max_by_key(|(_, child)| child.borrow().visits)
+                .map(|(mov, child)| (*mov, Rc::clone(child)));
+            let Some((our_move, next_node)) = best_child else {
+                break;
+            };
+
+            let opp_move = node_ref
+                .opponent_move_stats
+                .iter()
+                .filter(|(unique_move, _)| unique_move.pokemon_name == opp_active)
+                .max_by_key(|(_, stats)| stats.visits)
+                .map_or(MoveChoice::None, |(unique_move, _)| unique_move.move_choice);
+
+            (our_move, next_node, opp_move)
+        };
+
+        variation.push((
+            our_move.to_string(&pv_state.side_one),
+            opp_move.to_string(&pv_state.side_two),
         ));
+
+        let instructions =
+            generate_instructions_from_move_pair(&mut pv_state, &our_move, &opp_move, true);
+        if let Some(most_likely) = instructions
+            .iter()
+            .max_by(|a, b| a.percentage.total_cmp(&b.percentage))
+        {
+            pv_state.apply_instructions(&most_likely.instruction_list);
+        }
+
+        current = next_node;
     }
 
-    (policy, root.visits)
+    variation
+}
+
+fn choose_best_move(root: &MCTSNode, state: &State, config: &MctsConfig) -> SearchResult {
+    let (our_moves, opp_moves) = state.get_all_options();
+
+    // Collect statistics
+    let our_stats: Vec<_> = our_moves
+        .iter()
+        .map(|mov| match root.children.get(mov) {
+            Some(child) => {
+                let child_ref = child.borrow();
+                (*mov, child_ref.visits, child_ref.value)
+            }
+            None => (*mov, 0, 0.0),
+        })
+        .collect();
+
+    // Opponent values are stored from our side, flip them to the opponent's
+    let opp_stats: Vec<_> = opp_moves
+        .iter()
+        .map(|mov| {
+            let (visits, value) = root
+                .opponent_move_stats
+                .iter()
+                .filter(|(unique_move, _)| unique_move.move_choice == *mov)
+                .fold((0, 0.0), |(visits, value), (_, stats)| {
+                    (visits + stats.visits, value + stats.value)
+                });
+            (*mov, visits, visits as f32 - value)
+        })
+        .collect();
+
+    let mut result = SearchResult::new(
+        root_move_stats(&our_stats, &state.side_one),
+        root_move_stats(&opp_stats, &state.side_two),
+        root.visits,
+    );
+    result.choose_final_move(config);
+    result
 }
 
 fn should_stop(
@@ -468,6 +1212,7 @@ fn should_stop(
     iterations: Option<u32>,
     time_limit: Option<Duration>,
     mcts: &MCTS,
+    config: &MctsConfig,
 ) -> bool {
     let visits = mcts.root.borrow().visits;
 
@@ -483,5 +1228,43 @@ fn should_stop(
         }
     }
 
-    visits >= 10_000_000
+    visits >= config.max_visits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::perform_mcts_search_st;
+    use crate::mcts_config::MctsConfig;
+    use crate::mcts_result::SearchResult;
+    use poke_engine::state::State;
+
+    // Both sides' root moves with their visits and value, sorted by name
+    fn root_stats(result: &SearchResult) -> Vec<(String, i64, f32)> {
+        let mut stats: Vec<_> = result
+            .side_one
+            .iter()
+            .chain(&result.side_two)
+            .map(|s| (s.move_choice.clone(), s.visits, s.value))
+            .collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
+    #[test]
+    fn seeded_searches_build_the_same_tree() {
+        let config = MctsConfig {
+            seed: Some(7),
+            ..MctsConfig::default()
+        };
+        let search =
+            || perform_mcts_search_st(&mut State::default(), Some(500), None, &config, None);
+
+        let (first, second) = (search(), search());
+        assert_eq!(root_stats(&first), root_stats(&second));
+        assert_eq!(first.diagnostics.node_count, second.diagnostics.node_count);
+        assert_eq!(
+            first.diagnostics.principal_variation,
+            second.diagnostics.principal_variation
+        );
+    }
 }
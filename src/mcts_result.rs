@@ -0,0 +1,226 @@
+use poke_engine::state::{MoveChoice, Side};
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use std::time::Duration;
+
+use crate::mcts_config::{FinalMovePolicy, MctsConfig};
+
+// RNG stream for temperature sampling, apart from the ones the trees use
+const FINAL_MOVE_STREAM: u64 = u64::MAX;
+
+// Statistics for one root move. Values are from the point of view of the side making it.
+#[derive(Clone, Debug)]
+pub struct MoveStats {
+    pub move_choice: String,
+    pub visits: i64,
+    pub value: f32,
+    pub visit_share: f32,
+}
+
+impl MoveStats {
+    pub fn new(move_choice: String, visits: i64, value_sum: f32, total_visits: i64) -> Self {
+        MoveStats {
+            move_choice,
+            visits,
+            value: if visits > 0 {
+                value_sum / visits as f32
+            } else {
+                0.0
+            },
+            visit_share: if total_visits > 0 {
+                visits as f32 / total_visits as f32
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchDiagnostics {
+    pub max_depth: usize,
+    // mean depth of the node each iteration finished at
+    pub average_depth: f32,
+    pub node_count: usize,
+    // heap memory held by the trees and their tables at the end of the search
+    pub memory_bytes: usize,
+    pub elapsed_seconds: f64,
+    pub iterations_per_second: f64,
+    // iterations run on each tree, one tree per rayon thread
+    pub thread_visits: Vec<i64>,
+    // most visited (side one, side two) move at each ply from the root
+    pub principal_variation: Vec<(String, String)>,
+}
+
+impl SearchDiagnostics {
+    pub fn new(
+        elapsed: Duration,
+        thread_visits: Vec<i64>,
+        depth_sum: i64,
+        max_depth: usize,
+        node_count: usize,
+        memory_bytes: usize,
+        principal_variation: Vec<(String, String)>,
+    ) -> Self {
+        let iterations = thread_visits.iter().sum::<i64>();
+        let elapsed_seconds = elapsed.as_secs_f64();
+
+        SearchDiagnostics {
+            max_depth,
+            average_depth: if iterations > 0 {
+                depth_sum as f32 / iterations as f32
+            } else {
+                0.0
+            },
+            node_count,
+            memory_bytes,
+            elapsed_seconds,
+            iterations_per_second: if elapsed_seconds > 0.0 {
+                iterations as f64 / elapsed_seconds
+            } else {
+                0.0
+            },
+            thread_visits,
+            principal_variation,
+        }
+    }
+
+    // Folds in the diagnostics of a later search over the same trees
+    pub fn merge(&mut self, later: SearchDiagnostics) {
+        let iterations = self.thread_visits.iter().sum::<i64>();
+        let later_iterations = later.thread_visits.iter().sum::<i64>();
+        let total_iterations = iterations + later_iterations;
+
+        if total_iterations > 0 {
+            self.average_depth = (self.average_depth * iterations as f32
+                + later.average_depth * later_iterations as f32)
+                / total_iterations as f32;
+        }
+        self.max_depth = self.max_depth.max(later.max_depth);
+        self.node_count = later.node_count;
+        self.memory_bytes = later.memory_bytes;
+        self.elapsed_seconds += later.elapsed_seconds;
+        self.iterations_per_second = if self.elapsed_seconds > 0.0 {
+            total_iterations as f64 / self.elapsed_seconds
+        } else {
+            0.0
+        };
+
+        if self.thread_visits.len() < later.thread_visits.len() {
+            self.thread_visits.resize(later.thread_visits.len(), 0);
+        }
+        for (visits, later_visits) in self.thread_visits.iter_mut().zip(&later.thread_visits) {
+            *visits += later_visits;
+        }
+        self.principal_variation = later.principal_variation;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    // most visited move for the searching side and its mean value
+    pub best_move: String,
+    pub score: f32,
+    pub total_visits: i64,
+    pub side_one: Vec<MoveStats>,
+    // aggregated from the root's opponent move statistics
+    pub side_two: Vec<MoveStats>,
+    pub diagnostics: SearchDiagnostics,
+    // the searched tree down to the requested depth, when an export was asked for
+    pub tree_json: Option<String>,
+    pub tree_dot: Option<String>,
+}
+
+impl SearchResult {
+    pub fn new(side_one: Vec<MoveStats>, side_two: Vec<MoveStats>, total_visits: i64) -> Self {
+        let mut best_move = String::from("none");
+        let mut best_visits = 0;
+        let mut score = 0.0;
+
+        for stats in &side_one {
+            if stats.visits > best_visits {
+                best_visits = stats.visits;
+                best_move = stats.move_choice.clone();
+                score = stats.value;
+            }
+        }
+
+        SearchResult {
+            best_move,
+            score,
+            total_visits,
+            side_one,
+            side_two,
+            diagnostics: SearchDiagnostics::default(),
+            tree_json: None,
+            tree_dot: None,
+        }
+    }
+
+    // Re-picks best_move and score from side_one with the configured final move policy
+    pub fn choose_final_move(&mut self, config: &MctsConfig) {
+        let visited: Vec<&MoveStats> = self.side_one.iter().filter(|s| s.visits > 0).collect();
+        let max_visits = visited.iter().map(|s| s.visits).max().unwrap_or(0);
+        let max_value = visited.iter().map(|s| s.value).fold(0.0, f32::max);
+
+        let chosen = match config.final_move_policy {
+            FinalMovePolicy::MaxVisits => return,
+            FinalMovePolicy::Temperature if config.temperature == 0.0 => return,
+            FinalMovePolicy::MaxValue => visited
+                .iter()
+                .max_by(|a, b| a.value.total_cmp(&b.value))
+                .copied(),
+            FinalMovePolicy::RobustMax => visited
+                .iter()
+                .max_by(|a, b| {
+                    let robust = |s: &MoveStats| {
+                        let value_ratio = if max_value > 0.0 {
+                            s.value / max_value
+                        } else {
+                            1.0
+                        };
+                        (s.visits as f32 / max_visits as f32).min(value_ratio)
+                    };
+                    robust(a).total_cmp(&robust(b))
+                })
+                .copied(),
+            FinalMovePolicy::Temperature => {
+                // relative to the most visited move so small temperatures don't overflow
+                let weights = visited.iter().map(|s| {
+                    (s.visits as f64 / max_visits as f64).powf(1.0 / f64::from(config.temperature))
+                });
+                WeightedIndex::new(weights)
+                    .ok()
+                    .map(|dist| visited[dist.sample(&mut config.rng(FINAL_MOVE_STREAM))])
+            }
+        };
+
+        if let Some(stats) = chosen {
+            self.best_move = stats.move_choice.clone();
+            self.score = stats.value;
+        }
+    }
+
+    // Relabels a result searched on a mirrored state so side_one and side_two name
+    // the real sides again. best_move and score stay with the side that searched.
+    pub fn swap_sides(&mut self) {
+        std::mem::swap(&mut self.side_one, &mut self.side_two);
+        for (side_one_move, side_two_move) in &mut self.diagnostics.principal_variation {
+            std::mem::swap(side_one_move, side_two_move);
+        }
+    }
+}
+
+// Builds per-move statistics from (move, visits, value sum) tuples in legal move order.
+// MoveChoice::None is only listed when it was actually searched.
+pub fn root_move_stats(stats: &[(MoveChoice, i64, f32)], side: &Side) -> Vec<MoveStats> {
+    let total_visits = stats.iter().map(|(_, visits, _)| visits).sum::<i64>();
+
+    stats
+        .iter()
+        .filter(|(mov, visits, _)| *visits > 0 || *mov != MoveChoice::None)
+        .map(|(mov, visits, value)| {
+            MoveStats::new(mov.to_string(side), *visits, *value, total_visits)
+        })
+        .collect()
+}
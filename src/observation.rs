@@ -0,0 +1,94 @@
+use poke_engine::state::{MoveChoice, PokemonIndex, PokemonMoveIndex, PokemonStatus, State};
+
+// Slots in a network's move priors: the six move slots, then the six switches
+pub const ACTION_SPACE: usize = 12;
+
+pub fn action_index(move_choice: &MoveChoice) -> Option<usize> {
+    match move_choice {
+        MoveChoice::Move(index) => Some(match index {
+            PokemonMoveIndex::M0 => 0,
+            PokemonMoveIndex::M1 => 1,
+            PokemonMoveIndex::M2 => 2,
+            PokemonMoveIndex::M3 => 3,
+            PokemonMoveIndex::M4 => 4,
+            PokemonMoveIndex::M5 => 5,
+        }),
+        MoveChoice::Switch(index) => Some(match index {
+            PokemonIndex::P0 => 6,
+            PokemonIndex::P1 => 7,
+            PokemonIndex::P2 => 8,
+            PokemonIndex::P3 => 9,
+            PokemonIndex::P4 => 10,
+            PokemonIndex::P5 => 11,
+        }),
+        _ => None,
+    }
+}
+
+// One value of an observation. Flags and ids stay integers, as pokezoo.observations()
+// has always returned them.
+#[derive(Clone, Copy)]
+pub enum Feature {
+    Int(i32),
+    Float(f32),
+}
+
+impl Feature {
+    pub fn to_f32(self) -> f32 {
+        match self {
+            Feature::Int(v) => v as f32,
+            Feature::Float(v) => v,
+        }
+    }
+}
+
+// Features of the state as seen by side one and by side two, own side first
+pub fn encode_features(state: &State) -> [Vec<Feature>; 2] {
+    let mut res = [vec![], vec![]];
+
+    // this will change after considering what each side actually can know about the other side
+    for (i, side) in [
+        &state.side_one,
+        &state.side_two,
+        &state.side_two,
+        &state.side_one,
+    ]
+    .iter()
+    .enumerate()
+    {
+        let obs = &mut res[i % 2];
+
+        obs.push(Feature::Float(f32::from(side.attack_boost) / 6.0));
+        obs.push(Feature::Float(f32::from(side.defense_boost) / 6.0));
+        obs.push(Feature::Float(f32::from(side.evasion_boost) / 6.0));
+        obs.push(Feature::Float(f32::from(side.special_attack_boost) / 6.0));
+        obs.push(Feature::Float(f32::from(side.special_defense_boost) / 6.0));
+        obs.push(Feature::Float(f32::from(side.speed_boost) / 6.0));
+
+        for p in &side.pokemon {
+            // 0 for pokemon on this side, 1 for pokemon on other side
+            obs.push(Feature::Int((i / 2) as i32));
+
+            obs.push(Feature::Int(i32::from(Into::<u16>::into(p.id))));
+            obs.push(Feature::Float(f32::from(p.hp) / f32::from(p.maxhp)));
+
+            for status in [
+                PokemonStatus::BURN,
+                PokemonStatus::SLEEP,
+                PokemonStatus::FREEZE,
+                PokemonStatus::PARALYZE,
+                PokemonStatus::POISON,
+                PokemonStatus::TOXIC,
+            ] {
+                obs.push(Feature::Int(i32::from(p.status == status)));
+            }
+        }
+    }
+
+    res
+}
+
+// The features as the floats a network takes
+pub fn encode_observations(state: &State) -> [Vec<f32>; 2] {
+    encode_features(state).map(|obs| obs.into_iter().map(Feature::to_f32).collect())
+}
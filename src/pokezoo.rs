@@ -1,50 +1,22 @@
-use poke_engine::state::PokemonStatus;
 use pyo3::prelude::*;
 
+use crate::observation::{encode_features, Feature};
 use crate::pystate::PyState;
 
-#[pyfunction]
-pub fn observations(py_state: &PyState) -> Vec<Vec<PyObject>> {
-    let mut res = vec![vec![], vec![]];
-
-    Python::with_gil(|py| {
-        // generates a list of observations for each side
-        // this will change after considering what each side actually can know about the other side
-        for (i, side) in [
-            &py_state.state.side_one,
-            &py_state.state.side_two,
-            &py_state.state.side_two,
-            &py_state.state.side_one,
-        ]
-        .iter()
-        .enumerate()
-        {
-            let side_num = i % 2;
-
-            res[side_num].push((f32::from(side.attack_boost) / 6.0).to_object(py));
-            res[side_num].push((f32::from(side.defense_boost) / 6.0).to_object(py));
-            res[side_num].push((f32::from(side.evasion_boost) / 6.0).to_object(py));
-            res[side_num].push((f32::from(side.special_attack_boost) / 6.0).to_object(py));
-            res[side_num].push((f32::from(side.special_defense_boost) / 6.0).to_object(py));
-            res[side_num].push((f32::from(side.speed_boost) / 6.0).to_object(py));
-
-            for p in &side.pokemon {
-                // 0 for pokemon on this side, 1 for pokemon on other side
-                res[side_num].push((i / 2).to_object(py));
-
-                // res[side_num].push(p.pokedex_num.to_object(py));
-                res[side_num].push(Into::<u16>::into(p.id).clone().to_object(py));
-                res[side_num].push((f32::from(p.hp) / f32::from(p.maxhp)).to_object(py));
-
-                res[side_num].push(i32::from(p.status == PokemonStatus::BURN).to_object(py));
-                res[side_num].push(i32::from(p.status == PokemonStatus::SLEEP).to_object(py));
-                res[side_num].push(i32::from(p.status == PokemonStatus::FREEZE).to_object(py));
-                res[side_num].push(i32::from(p.status == PokemonStatus::PARALYZE).to_object(py));
-                res[side_num].push(i32::from(p.status == PokemonStatus::POISON).to_object(py));
-                res[side_num].push(i32::from(p.status == PokemonStatus::TOXIC).to_object(py));
-            }
+impl ToPyObject for Feature {
+    fn to_object(&self, py: Python<'_>) -> PyObject {
+        match self {
+            Feature::Int(v) => v.to_object(py),
+            Feature::Float(v) => v.to_object(py),
         }
-    });
+    }
+}
 
-    res
+// generates a list of observations for each side
+#[pyfunction]
+pub fn observations(py: Python<'_>, py_state: &PyState) -> Vec<Vec<PyObject>> {
+    encode_features(&py_state.state)
+        .iter()
+        .map(|obs| obs.iter().map(|f| f.to_object(py)).collect())
+        .collect()
 }
@@ -0,0 +1,198 @@
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::str::FromStr;
+
+use crate::mcts_config::{
+    FinalMovePolicy, MctsConfig, ParallelMode, RolloutPolicy, RolloutWeights, SimultaneousPolicy,
+};
+
+#[derive(Clone, Default)]
+#[pyclass(name = "MctsConfig")]
+pub struct PyMctsConfig {
+    pub config: MctsConfig,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[pymethods]
+impl PyMctsConfig {
+    /// # Errors
+    /// - Negative exploration or PUCT constant, or negative progressive bias
+    /// - Non-positive sigmoid scale, batch size or visit cap
+    /// - Invalid simultaneous policy
+    /// - Exploration rate outside (0, 1]
+    /// - Invalid parallel mode
+    /// - Invalid rollout policy
+    /// - Invalid final move policy or negative temperature
+    /// - Non-positive Dirichlet alpha or epsilon outside [0, 1]
+    /// - A node or memory budget of 0
+    ///
+    /// `rollout_weights` are the (damage, evaluation, switch) weights of the
+    /// heuristic rollout policy. `progressive_bias` weighs the expected damage of each
+    /// of our moves at the root into UCB1 selection and expands the most damaging
+    /// moves first.
+    /// Searches reject the options they don't use: Dirichlet noise is only mixed into
+    /// the PUCT search's priors, `transpositions` and `solver` only apply to
+    /// `perform_mcts_search_st`, and `max_nodes` and `max_memory_bytes` only to
+    /// `perform_mcts_search`, `SearchSession` and `SearchHandle`.
+    #[new]
+    #[pyo3(signature = (
+        exploration_constant=2.0,
+        opponent_exploration_constant=2.0,
+        sigmoid_scale=0.0125,
+        batch_size=20,
+        max_visits=10_000_000,
+        simultaneous_policy="regret_matching",
+        exploration_rate=0.1,
+        chance_nodes=false,
+        parallel_mode="root",
+        puct_constant=1.5,
+        rollout_policy="none",
+        rollout_depth=20,
+        rollout_weights=(1.0, 0.0, 0.0),
+        final_move_policy="max_visits",
+        temperature=1.0,
+        dirichlet_alpha=0.3,
+        dirichlet_epsilon=0.0,
+        transpositions=false,
+        solver=false,
+        max_nodes=None,
+        max_memory_bytes=None,
+        prune=true,
+        progressive_bias=0.0,
+    ))]
+    fn new(
+        exploration_constant: f32,
+        opponent_exploration_constant: f32,
+        sigmoid_scale: f32,
+        batch_size: u32,
+        max_visits: i64,
+        simultaneous_policy: &str,
+        exploration_rate: f32,
+        chance_nodes: bool,
+        parallel_mode: &str,
+        puct_constant: f32,
+        rollout_policy: &str,
+        rollout_depth: usize,
+        rollout_weights: (f32, f32, f32),
+        final_move_policy: &str,
+        temperature: f32,
+        dirichlet_alpha: f32,
+        dirichlet_epsilon: f32,
+        transpositions: bool,
+        solver: bool,
+        max_nodes: Option<usize>,
+        max_memory_bytes: Option<usize>,
+        prune: bool,
+        progressive_bias: f32,
+    ) -> PyResult<Self> {
+        if exploration_constant < 0.0 || opponent_exploration_constant < 0.0 || puct_constant < 0.0
+        {
+            return Err(PyValueError::new_err(
+                "Exploration constants must be non-negative",
+            ));
+        }
+        if progressive_bias < 0.0 {
+            return Err(PyValueError::new_err(format!(
+                "Invalid progressive_bias: {progressive_bias}"
+            )));
+        }
+        if sigmoid_scale <= 0.0 {
+            return Err(PyValueError::new_err(format!(
+                "Invalid sigmoid_scale: {sigmoid_scale}"
+            )));
+        }
+        if batch_size == 0 {
+            return Err(PyValueError::new_err("batch_size must be at least 1"));
+        }
+        if max_visits <= 0 {
+            return Err(PyValueError::new_err(format!(
+                "Invalid max_visits: {max_visits}"
+            )));
+        }
+        if exploration_rate <= 0.0 || exploration_rate > 1.0 {
+            return Err(PyValueError::new_err(format!(
+                "Invalid exploration_rate: {exploration_rate}"
+            )));
+        }
+        if temperature < 0.0 {
+            return Err(PyValueError::new_err(format!(
+                "Invalid temperature: {temperature}"
+            )));
+        }
+        if dirichlet_alpha <= 0.0 || !(0.0..=1.0).contains(&dirichlet_epsilon) {
+            return Err(PyValueError::new_err(format!(
+                "Invalid Dirichlet noise: alpha {dirichlet_alpha}, epsilon {dirichlet_epsilon}"
+            )));
+        }
+        if max_nodes == Some(0) || max_memory_bytes == Some(0) {
+            return Err(PyValueError::new_err(
+                "Node and memory budgets must be at least 1",
+            ));
+        }
+
+        Ok(Self {
+            config: MctsConfig {
+                exploration_constant,
+                opponent_exploration_constant,
+                sigmoid_scale,
+                batch_size,
+                max_visits,
+                simultaneous_policy: match SimultaneousPolicy::from_str(simultaneous_policy) {
+                    Ok(p) => p,
+                    Err(()) => {
+                        return Err(PyValueError::new_err(format!(
+                            "Invalid simultaneous_policy: {simultaneous_policy}"
+                        )))
+                    }
+                },
+                exploration_rate,
+                chance_nodes,
+                parallel_mode: match ParallelMode::from_str(parallel_mode) {
+                    Ok(m) => m,
+                    Err(()) => {
+                        return Err(PyValueError::new_err(format!(
+                            "Invalid parallel_mode: {parallel_mode}"
+                        )))
+                    }
+                },
+                puct_constant,
+                rollout_policy: match RolloutPolicy::from_str(rollout_policy) {
+                    Ok(p) => p,
+                    Err(()) => {
+                        return Err(PyValueError::new_err(format!(
+                            "Invalid rollout_policy: {rollout_policy}"
+                        )))
+                    }
+                },
+                rollout_depth,
+                rollout_weights: RolloutWeights {
+                    damage: rollout_weights.0,
+                    evaluation: rollout_weights.1,
+                    switch: rollout_weights.2,
+                },
+                final_move_policy: match FinalMovePolicy::from_str(final_move_policy) {
+                    Ok(p) => p,
+                    Err(()) => {
+                        return Err(PyValueError::new_err(format!(
+                            "Invalid final_move_policy: {final_move_policy}"
+                        )))
+                    }
+                },
+                temperature,
+                dirichlet_alpha,
+                dirichlet_epsilon,
+                transpositions,
+                solver,
+                max_nodes,
+                max_memory_bytes,
+                prune,
+                progressive_bias,
+                // set per search through the `seed` argument
+                seed: None,
+            },
+        })
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self.config)
+    }
+}
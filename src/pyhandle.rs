@@ -0,0 +1,196 @@
+use poke_engine::state::State;
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use rayon::ThreadPool;
+
+use crate::mcts_config::MctsConfig;
+use crate::mcts_ol::{choose_best_move, new_trees, run_in_pool, search_trees};
+use crate::mcts_result::{SearchDiagnostics, SearchResult};
+use crate::{
+    pyconfig::PyMctsConfig,
+    pysearch::PySearchResult,
+    pystate::{search_config, search_thread_pool, PyState, Search},
+};
+
+// How often the worker publishes the current best move
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(50);
+
+// Searches in short slices, publishing a result after each one, until the budget
+// runs out or `stop` is set
+fn run_search(
+    state: State,
+    iterations: Option<u32>,
+    time_limit: Option<Duration>,
+    config: MctsConfig,
+    pool: Option<Arc<ThreadPool>>,
+    stop: Arc<AtomicBool>,
+    latest: Arc<Mutex<Option<SearchResult>>>,
+) {
+    let start_time = Instant::now();
+    let mut trees = run_in_pool(pool.as_deref(), || new_trees(&config));
+    let mut diagnostics = SearchDiagnostics::default();
+    let mut remaining_iterations = iterations;
+    let mut slice_config = config.clone();
+
+    loop {
+        // Move each slice onto fresh streams so they don't replay the same numbers
+        slice_config.seed = config
+            .seed
+            .map(|seed| seed.wrapping_add(diagnostics.thread_visits.iter().sum::<i64>() as u64));
+
+        let slice = time_limit.map_or(SNAPSHOT_INTERVAL, |limit| {
+            limit
+                .saturating_sub(start_time.elapsed())
+                .min(SNAPSHOT_INTERVAL)
+        });
+
+        let slice_diagnostics = run_in_pool(pool.as_deref(), || {
+            search_trees(
+                &mut trees,
+                &state,
+                remaining_iterations,
+                Some(slice),
+                &slice_config,
+                &stop,
+            )
+        });
+        let slice_iterations = slice_diagnostics.thread_visits.iter().sum::<i64>();
+        diagnostics.merge(slice_diagnostics);
+        remaining_iterations = remaining_iterations
+            .map(|i| i.saturating_sub(u32::try_from(slice_iterations).unwrap_or(u32::MAX)));
+
+        let mut result = choose_best_move(&trees, &state, &config);
+        result.diagnostics = diagnostics.clone();
+        *latest.lock().unwrap() = Some(result);
+
+        // An empty slice means the visit cap was reached
+        if stop.load(Ordering::Relaxed)
+            || remaining_iterations == Some(0)
+            || time_limit.is_some_and(|limit| start_time.elapsed() >= limit)
+            || slice_iterations == 0
+        {
+            break;
+        }
+    }
+}
+
+#[pyclass(name = "SearchHandle")]
+pub struct PySearchHandle {
+    stop: Arc<AtomicBool>,
+
+    // result as of the last finished slice, None until the first one ends
+    latest: Arc<Mutex<Option<SearchResult>>>,
+
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PySearchHandle {
+    // Waits for the worker without holding the GIL and returns its final result
+    fn join(&mut self, py: Python<'_>) -> PyResult<PySearchResult> {
+        if let Some(worker) = self.worker.take() {
+            if py.allow_threads(|| worker.join()).is_err() {
+                return Err(PyRuntimeError::new_err("Search thread panicked"));
+            }
+        }
+
+        match &*self.latest.lock().unwrap() {
+            Some(result) => Ok(PySearchResult::from_search_result(result)),
+            None => Err(PyRuntimeError::new_err("Search finished without a result")),
+        }
+    }
+}
+
+#[pymethods]
+impl PySearchHandle {
+    /// Starts a parallel MCTS search on a background thread and returns immediately.
+    /// Without `time_limit` or `iterations` it runs until `stop()` or the visit cap.
+    /// `seed` fixes the random choices, though slices still end on the clock.
+    ///
+    /// # Errors
+    /// - `num_threads` is 0 or the thread pool can't be built
+    /// - `config` asks for Dirichlet noise, transpositions or the solver, which this
+    ///   search doesn't use
+    #[new]
+    #[pyo3(signature = (
+        state,
+        time_limit=None,
+        iterations=None,
+        config=None,
+        num_threads=None,
+        seed=None,
+    ))]
+    fn new(
+        state: &PyState,
+        time_limit: Option<u64>,
+        iterations: Option<u32>,
+        config: Option<PyMctsConfig>,
+        num_threads: Option<usize>,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        let mut config = search_config(config, Search::Parallel)?;
+        config.seed = seed;
+        let pool = search_thread_pool(num_threads)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let latest = Arc::new(Mutex::new(None));
+
+        let worker = {
+            let state = state.state.clone();
+            let time_limit = time_limit.map(Duration::from_millis);
+            let stop = Arc::clone(&stop);
+            let latest = Arc::clone(&latest);
+            std::thread::spawn(move || {
+                run_search(state, iterations, time_limit, config, pool, stop, latest);
+            })
+        };
+
+        Ok(Self {
+            stop,
+            latest,
+            worker: Some(worker),
+        })
+    }
+
+    /// Returns the result so far without interrupting the search, or None
+    /// if no result has been published yet
+    fn poll(&self) -> Option<PySearchResult> {
+        self.latest
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(PySearchResult::from_search_result)
+    }
+
+    fn is_running(&self) -> bool {
+        self.worker
+            .as_ref()
+            .is_some_and(|worker| !worker.is_finished())
+    }
+
+    /// Stops the search at the next batch and returns the final result
+    ///
+    /// # Errors
+    /// - The search thread panicked
+    fn stop(&mut self, py: Python<'_>) -> PyResult<PySearchResult> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.join(py)
+    }
+
+    /// Waits for the search to use up its budget and returns the final result
+    ///
+    /// # Errors
+    /// - The search thread panicked
+    fn wait(&mut self, py: Python<'_>) -> PyResult<PySearchResult> {
+        self.join(py)
+    }
+}
+
+impl Drop for PySearchHandle {
+    // Don't leave an abandoned search running in the background
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,103 @@
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::mcts_config::Perspective;
+use crate::mcts_ol_st::PuctSearch;
+use crate::observation::encode_observations;
+use crate::{
+    pyconfig::PyMctsConfig,
+    pysearch::PySearchResult,
+    pystate::{leaf_evaluations, search_config, search_perspective, PyState, Search},
+};
+
+// The tree holds Rc nodes, so a search stays on the thread that created it
+#[pyclass(name = "PuctSearch", unsendable)]
+pub struct PyPuctSearch {
+    search: PuctSearch,
+    perspective: Perspective,
+}
+
+#[allow(clippy::needless_pass_by_value)]
+#[pymethods]
+impl PyPuctSearch {
+    /// A PUCT search driven one batch at a time: `select_leaves` asks for leaves to
+    /// evaluate and `submit` tells the search what the network made of them.
+    ///
+    /// # Errors
+    /// - Invalid perspective
+    /// - `config` asks for transpositions, the solver or a node or memory budget, which
+    ///   this search doesn't use
+    #[new]
+    #[pyo3(signature = (state, config=None, seed=None, perspective="side_one"))]
+    fn new(
+        state: &PyState,
+        config: Option<PyMctsConfig>,
+        seed: Option<u64>,
+        perspective: &str,
+    ) -> PyResult<Self> {
+        let perspective = search_perspective(perspective)?;
+        let mut config = search_config(config, Search::Puct)?;
+        config.seed = seed;
+
+        Ok(Self {
+            search: PuctSearch::new(perspective.orient(&state.state), config),
+            perspective,
+        })
+    }
+
+    /// Selects up to `n` leaves and returns their observations from the searching
+    /// side's view. Fewer come back when some leaves end the battle, the visit cap is
+    /// reached or the root still needs its first evaluation.
+    ///
+    /// # Errors
+    /// - Leaves from the previous call haven't been submitted
+    fn select_leaves(&mut self, n: usize) -> PyResult<Vec<Vec<f32>>> {
+        if self.search.pending_count() > 0 {
+            return Err(PyValueError::new_err(
+                "Submit the pending leaves before selecting more",
+            ));
+        }
+        self.search.select_leaves(n);
+
+        Ok(self
+            .search
+            .pending_states()
+            .into_iter()
+            .map(|state| {
+                let [observation, _] = encode_observations(state);
+                observation
+            })
+            .collect())
+    }
+
+    /// Backs up the network's output for the leaves from the last `select_leaves`:
+    /// one win probability in [0, 1] and one list of ACTION_SPACE priors per leaf
+    ///
+    /// # Errors
+    /// - `values` or `priors` don't match the pending leaves
+    /// - A NaN or infinite value or prior
+    fn submit(&mut self, values: Vec<f32>, priors: Vec<Vec<f32>>) -> PyResult<()> {
+        let evaluations = leaf_evaluations(priors, values, self.search.pending_count())?;
+        self.search.submit(&evaluations);
+        Ok(())
+    }
+
+    #[getter]
+    fn num_pending(&self) -> usize {
+        self.search.pending_count()
+    }
+
+    #[getter]
+    fn visits(&self) -> i64 {
+        self.search.visits()
+    }
+
+    /// Returns the search so far. Leaves still pending count as visits.
+    #[pyo3(signature = (export_depth=None))]
+    fn result(&self, export_depth: Option<usize>) -> PySearchResult {
+        let mut result = self.search.result(export_depth);
+        if self.perspective == Perspective::SideTwo {
+            result.swap_sides();
+        }
+        PySearchResult::from_search_result(&result)
+    }
+}
@@ -0,0 +1,108 @@
+use pyo3::prelude::*;
+
+use crate::mcts_result::{MoveStats, SearchDiagnostics, SearchResult};
+
+#[derive(Clone, Debug)]
+#[pyclass(name = "MoveStats", get_all)]
+pub struct PyMoveStats {
+    pub move_choice: String,
+    pub visits: i64,
+    pub value: f32,
+    pub visit_share: f32,
+}
+
+impl PyMoveStats {
+    fn from_move_stats(stats: &MoveStats) -> Self {
+        Self {
+            move_choice: stats.move_choice.clone(),
+            visits: stats.visits,
+            value: stats.value,
+            visit_share: stats.visit_share,
+        }
+    }
+}
+
+#[pymethods]
+impl PyMoveStats {
+    fn __str__(&self) -> String {
+        format!("{self:#?}")
+    }
+}
+
+#[derive(Clone, Debug)]
+#[pyclass(name = "SearchDiagnostics", get_all)]
+pub struct PySearchDiagnostics {
+    pub max_depth: usize,
+    pub average_depth: f32,
+    pub node_count: usize,
+    pub memory_bytes: usize,
+    pub elapsed_seconds: f64,
+    pub iterations_per_second: f64,
+    pub thread_visits: Vec<i64>,
+    pub principal_variation: Vec<(String, String)>,
+}
+
+impl PySearchDiagnostics {
+    fn from_search_diagnostics(diagnostics: &SearchDiagnostics) -> Self {
+        Self {
+            max_depth: diagnostics.max_depth,
+            average_depth: diagnostics.average_depth,
+            node_count: diagnostics.node_count,
+            memory_bytes: diagnostics.memory_bytes,
+            elapsed_seconds: diagnostics.elapsed_seconds,
+            iterations_per_second: diagnostics.iterations_per_second,
+            thread_visits: diagnostics.thread_visits.clone(),
+            principal_variation: diagnostics.principal_variation.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl PySearchDiagnostics {
+    fn __str__(&self) -> String {
+        format!("{self:#?}")
+    }
+}
+
+#[derive(Clone, Debug)]
+#[pyclass(name = "SearchResult", get_all)]
+pub struct PySearchResult {
+    pub best_move: String,
+    pub score: f32,
+    pub total_visits: i64,
+    pub side_one: Vec<PyMoveStats>,
+    pub side_two: Vec<PyMoveStats>,
+    pub diagnostics: PySearchDiagnostics,
+    pub tree_json: Option<String>,
+    pub tree_dot: Option<String>,
+}
+
+impl PySearchResult {
+    pub fn from_search_result(result: &SearchResult) -> Self {
+        Self {
+            best_move: result.best_move.clone(),
+            score: result.score,
+            total_visits: result.total_visits,
+            side_one: result
+                .side_one
+                .iter()
+                .map(PyMoveStats::from_move_stats)
+                .collect(),
+            side_two: result
+                .side_two
+                .iter()
+                .map(PyMoveStats::from_move_stats)
+                .collect(),
+            diagnostics: PySearchDiagnostics::from_search_diagnostics(&result.diagnostics),
+            tree_json: result.tree_json.clone(),
+            tree_dot: result.tree_dot.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl PySearchResult {
+    fn __str__(&self) -> String {
+        format!("{self:#?}")
+    }
+}
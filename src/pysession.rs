@@ -0,0 +1,347 @@
+use poke_engine::{
+    generate_instructions::generate_instructions_from_move_pair,
+    state::{MoveChoice, State},
+};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use rayon::ThreadPool;
+
+use crate::mcts_config::MctsConfig;
+use crate::mcts_ol::{
+    choose_best_move, export_tree, likely_replies, new_trees, run_in_pool, search_trees, MCTS,
+};
+use crate::valid_moves::ValidMoves;
+use crate::{
+    pyconfig::PyMctsConfig,
+    pysearch::PySearchResult,
+    pystate::{search_config, search_thread_pool, search_time_limit, PyState, Search},
+};
+
+#[pyclass(name = "SearchSession")]
+pub struct PySearchSession {
+    state: State,
+    config: MctsConfig,
+
+    // one tree per rayon thread, or a single shared one, kept between turns
+    trees: Vec<MCTS>,
+
+    // pool for `num_threads`, shared with the ponder thread and any other search of
+    // that size
+    pool: Option<Arc<ThreadPool>>,
+
+    // background search of the likely next positions while we wait for the opponent
+    ponder: Option<Ponder>,
+}
+
+// Opponent replies searched while pondering, and iterations given to each in turn
+const PONDER_REPLIES: usize = 3;
+const PONDER_SLICE: u32 = 1000;
+
+struct Ponder {
+    stop: Arc<AtomicBool>,
+    our_move: MoveChoice,
+    worker: JoinHandle<Vec<PonderLine>>,
+}
+
+// The position after our submitted move, one opponent reply and that joint move's
+// most likely instruction branch, with its own trees
+struct PonderLine {
+    opp_move: MoveChoice,
+    branch: usize,
+    state: State,
+    trees: Vec<MCTS>,
+}
+
+impl PySearchSession {
+    fn new_trees(&self) -> Vec<MCTS> {
+        run_in_pool(self.pool.as_deref(), || new_trees(&self.config))
+    }
+
+    // Ends any background search, returning our pondered move and the lines searched
+    fn finish_pondering(
+        &mut self,
+        py: Python<'_>,
+    ) -> PyResult<Option<(MoveChoice, Vec<PonderLine>)>> {
+        let Some(ponder) = self.ponder.take() else {
+            return Ok(None);
+        };
+
+        ponder.stop.store(true, Ordering::Relaxed);
+        match py.allow_threads(|| ponder.worker.join()) {
+            Ok(lines) => Ok(Some((ponder.our_move, lines))),
+            Err(_) => Err(PyRuntimeError::new_err("Ponder thread panicked")),
+        }
+    }
+
+    // Sets up the positions to ponder after our move, from the opponent's most visited
+    // replies, or their first moves in a tree that hasn't searched any yet
+    fn ponder_lines(&mut self, our_move: MoveChoice) -> Vec<PonderLine> {
+        let mut replies = likely_replies(&self.trees);
+        if replies.is_empty() {
+            replies = ValidMoves::new(&self.state).theirs;
+        }
+        replies.truncate(PONDER_REPLIES);
+
+        let mut lines = Vec::with_capacity(replies.len());
+        for opp_move in replies {
+            let instructions =
+                generate_instructions_from_move_pair(&mut self.state, &our_move, &opp_move, true);
+            let Some((branch, most_likely)) = instructions
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.percentage.total_cmp(&b.percentage))
+            else {
+                continue;
+            };
+
+            let mut state = self.state.clone();
+            state.apply_instructions(&most_likely.instruction_list);
+            lines.push(PonderLine {
+                opp_move,
+                branch,
+                state,
+                trees: self.new_trees(),
+            });
+        }
+        lines
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+#[pymethods]
+impl PySearchSession {
+    /// # Errors
+    /// - `num_threads` is 0 or the thread pool can't be built
+    /// - `config` asks for Dirichlet noise, transpositions or the solver, which this
+    ///   search doesn't use
+    #[new]
+    #[pyo3(signature = (state, config=None, num_threads=None))]
+    fn new(
+        state: &PyState,
+        config: Option<PyMctsConfig>,
+        num_threads: Option<usize>,
+    ) -> PyResult<Self> {
+        let mut session = Self {
+            state: state.state.clone(),
+            config: search_config(config, Search::Parallel)?,
+            trees: vec![],
+            pool: search_thread_pool(num_threads)?,
+            ponder: None,
+        };
+        session.trees = session.new_trees();
+        Ok(session)
+    }
+
+    /// Continues searching from the current root, keeping everything already in the tree.
+    /// Stops pondering first if a background search is running. A `seed` makes the
+    /// search reproducible given the same session history.
+    ///
+    /// # Errors
+    /// - Neither `time_limit` nor `iterations` given
+    /// - The ponder thread panicked
+    #[pyo3(signature = (time_limit=None, iterations=None, export_depth=None, seed=None))]
+    fn search(
+        &mut self,
+        py: Python<'_>,
+        time_limit: Option<u64>,
+        iterations: Option<u32>,
+        export_depth: Option<usize>,
+        seed: Option<u64>,
+    ) -> PyResult<PySearchResult> {
+        let time_limit = search_time_limit(time_limit, iterations)?;
+        self.finish_pondering(py)?;
+        let mut config = self.config.clone();
+        config.seed = seed;
+
+        let diagnostics = py.allow_threads(|| {
+            run_in_pool(self.pool.as_deref(), || {
+                search_trees(
+                    &mut self.trees,
+                    &self.state,
+                    iterations,
+                    time_limit,
+                    &config,
+                    &AtomicBool::new(false),
+                )
+            })
+        });
+
+        let mut result = choose_best_move(&self.trees, &self.state, &config);
+        result.diagnostics = diagnostics;
+        if let Some(plies) = export_depth {
+            export_tree(&mut result, &self.trees, &self.state, plies);
+        }
+
+        Ok(PySearchResult::from_search_result(&result))
+    }
+
+    /// Plays the joint move, applies the observed branch from `generate_instructions`
+    /// and promotes the matching subtree to be the new root. A position searched while
+    /// pondering takes over when the move, reply and branch all match it. The trees are
+    /// open-loop, so otherwise a subtree is only kept when every visit to it came
+    /// through this opponent move and branch, and the search starts over if not.
+    ///
+    /// # Errors
+    /// - Invalid move for either side
+    /// - Invalid instruction index
+    /// - The ponder thread panicked
+    fn advance(
+        &mut self,
+        py: Python<'_>,
+        side_one_move: String,
+        side_two_move: String,
+        instruction_index: usize,
+    ) -> PyResult<()> {
+        let Some(s1_move) = self.state.side_one.string_to_movechoice(&side_one_move) else {
+            return Err(PyValueError::new_err(format!(
+                "Invalid move for s1: {side_one_move}"
+            )));
+        };
+
+        let Some(s2_move) = self.state.side_two.string_to_movechoice(&side_two_move) else {
+            return Err(PyValueError::new_err(format!(
+                "Invalid move for s2: {side_two_move}"
+            )));
+        };
+
+        let pondered = self.finish_pondering(py)?;
+
+        let instructions =
+            generate_instructions_from_move_pair(&mut self.state, &s1_move, &s2_move, true);
+
+        let Some(instructions) = instructions.get(instruction_index) else {
+            return Err(PyValueError::new_err(format!(
+                "Invalid index: {instruction_index}"
+            )));
+        };
+
+        self.state
+            .apply_instructions(&instructions.instruction_list);
+
+        let pondered_line = pondered
+            .filter(|(our_move, _)| *our_move == s1_move)
+            .and_then(|(_, lines)| {
+                lines
+                    .into_iter()
+                    .find(|line| line.opp_move == s2_move && line.branch == instruction_index)
+            });
+        match pondered_line {
+            Some(line) => self.trees = line.trees,
+            None => {
+                for tree in &mut self.trees {
+                    tree.advance(&s1_move, &s2_move, instruction_index);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Searches the positions after our submitted move and the opponent's most likely
+    /// replies in a background thread, taking turns between them, until `advance`,
+    /// `search`, `stop_pondering` or `reset` is called. Each reply is followed by its
+    /// most likely instruction branch. Runs until every position reaches the visit
+    /// cap if nothing stops it.
+    ///
+    /// # Errors
+    /// - Invalid move for s1
+    /// - Already pondering
+    fn ponder(&mut self, side_one_move: String) -> PyResult<()> {
+        if self.ponder.is_some() {
+            return Err(PyValueError::new_err("Already pondering"));
+        }
+
+        let Some(s1_move) = self.state.side_one.string_to_movechoice(&side_one_move) else {
+            return Err(PyValueError::new_err(format!(
+                "Invalid move for s1: {side_one_move}"
+            )));
+        };
+
+        let mut lines = self.ponder_lines(s1_move);
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let config = self.config.clone();
+            let pool = self.pool.clone();
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                run_in_pool(pool.as_deref(), || {
+                    ponder_round_robin(&mut lines, &config, &stop);
+                });
+                lines
+            })
+        };
+
+        self.ponder = Some(Ponder {
+            stop,
+            our_move: s1_move,
+            worker,
+        });
+        Ok(())
+    }
+
+    /// # Errors
+    /// - The ponder thread panicked
+    fn stop_pondering(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.finish_pondering(py)?;
+        Ok(())
+    }
+
+    fn is_pondering(&self) -> bool {
+        self.ponder.is_some()
+    }
+
+    /// Replaces the position and discards the tree, e.g. when new information is revealed
+    ///
+    /// # Errors
+    /// - The ponder thread panicked
+    fn reset(&mut self, py: Python<'_>, state: &PyState) -> PyResult<()> {
+        self.finish_pondering(py)?;
+        self.state = state.state.clone();
+        self.trees = self.new_trees();
+        Ok(())
+    }
+
+    fn get_state(&self) -> PyState {
+        PyState::from_state(self.state.clone())
+    }
+}
+
+// Gives each pondered position a slice of iterations in turn until stopped, or until
+// none of them can be searched further
+fn ponder_round_robin(lines: &mut [PonderLine], config: &MctsConfig, stop: &AtomicBool) {
+    loop {
+        let mut searched = false;
+        for line in lines.iter_mut() {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            let diagnostics = search_trees(
+                &mut line.trees,
+                &line.state,
+                Some(PONDER_SLICE),
+                None,
+                config,
+                stop,
+            );
+            searched |= diagnostics.thread_visits.iter().sum::<i64>() > 0;
+        }
+        if !searched {
+            return;
+        }
+    }
+}
+
+impl Drop for PySearchSession {
+    // Don't leave a ponder thread running once the session is gone
+    fn drop(&mut self) {
+        if let Some(ponder) = &self.ponder {
+            ponder.stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
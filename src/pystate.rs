@@ -1,5 +1,9 @@
-use crate::mcts_ol::perform_mcts_search;
-use crate::mcts_ol_st::perform_mcts_search_st;
+use crate::mcts_config::{MctsConfig, Perspective};
+use crate::mcts_duct::{perform_simultaneous_search, SimultaneousResult};
+use crate::mcts_ol::{perform_mcts_search, run_in_pool};
+use crate::mcts_ol_st::{perform_mcts_search_st, perform_puct_search, LeafEvaluation};
+use crate::observation::{encode_observations, ACTION_SPACE};
+use crate::state_hash::state_hash;
 use poke_engine::{
     evaluate::evaluate,
     generate_instructions::generate_instructions_from_move_pair,
@@ -8,11 +12,19 @@ use poke_engine::{
         MoveChoice, Side, State, StateTerrain, StateTrickRoom, StateWeather, Terrain, Weather,
     },
 };
-use pyo3::{exceptions::PyValueError, prelude::*};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::BTreeMap;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use crate::{pymove::PyMoveChoice, pyside::PySide};
+use crate::{
+    pyconfig::PyMctsConfig, pymove::PyMoveChoice, pysearch::PySearchResult, pyside::PySide,
+};
 
 #[pyclass(name = "State")]
 pub struct PyState {
@@ -25,6 +37,16 @@ pub struct PyState {
     instruction_stack: Vec<StateInstructions>,
 }
 
+impl PyState {
+    pub fn from_state(state: State) -> Self {
+        Self {
+            state,
+            prev_instructions: None,
+            instruction_stack: vec![],
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments, clippy::needless_pass_by_value)]
 #[pymethods]
 impl PyState {
@@ -208,29 +230,342 @@ impl PyState {
         format!("{:#?}", self.state)
     }
 
-    fn perform_mcts_search(&mut self, time_limit: u64) -> (String, f32, i64) {
-        // Convert time_limit from seconds to Duration if provided
-        let time_limit = Duration::from_millis(time_limit);
+    /// Returns per-move statistics for both sides and the number of iterations that ran.
+    /// With `export_depth`, the tree down to that many plies is attached as JSON and DOT.
+    /// `num_threads` runs the search on a pool of that many threads instead of the
+    /// global one. Each size's pool is built once and reused by later searches.
+    /// A `seed` makes the search reproducible for a fixed iteration and thread count.
+    /// `perspective="side_two"` searches for side two, `best_move` is then side two's.
+    ///
+    /// # Errors
+    /// - Neither `time_limit` nor `iterations` given
+    /// - `num_threads` is 0 or the thread pool can't be built
+    /// - Invalid perspective
+    /// - `config` asks for Dirichlet noise, transpositions or the solver, which this
+    ///   search doesn't use
+    #[pyo3(signature = (
+        time_limit=None,
+        iterations=None,
+        config=None,
+        export_depth=None,
+        num_threads=None,
+        seed=None,
+        perspective="side_one",
+    ))]
+    fn perform_mcts_search(
+        &self,
+        py: Python<'_>,
+        time_limit: Option<u64>,
+        iterations: Option<u32>,
+        config: Option<PyMctsConfig>,
+        export_depth: Option<usize>,
+        num_threads: Option<usize>,
+        seed: Option<u64>,
+        perspective: &str,
+    ) -> PyResult<PySearchResult> {
+        let time_limit = search_time_limit(time_limit, iterations)?;
+        let perspective = search_perspective(perspective)?;
+        let mut config = search_config(config, Search::Parallel)?;
+        config.seed = seed;
+        let pool = search_thread_pool(num_threads)?;
+        let mut search_state = perspective.orient(&self.state);
+
+        let mut result = py.allow_threads(|| {
+            run_in_pool(pool.as_deref(), || {
+                perform_mcts_search(
+                    &mut search_state,
+                    iterations,
+                    time_limit,
+                    &config,
+                    export_depth,
+                )
+            })
+        });
+        if perspective == Perspective::SideTwo {
+            result.swap_sides();
+        }
 
-        perform_mcts_search(&mut self.state, None, Some(time_limit))
+        Ok(PySearchResult::from_search_result(&result))
     }
 
-    fn perform_mcts_search_st(&mut self, time_limit: u64) -> (Vec<(String, f32)>, i64) {
-        // Convert time_limit from seconds to Duration if provided
-        let time_limit = Duration::from_millis(time_limit);
+    /// Returns per-move statistics for both sides and the number of iterations that ran.
+    /// With `export_depth`, the tree down to that many plies is attached as JSON and DOT.
+    /// A `seed` makes the search reproducible for a fixed iteration count.
+    /// `perspective="side_two"` searches for side two, `best_move` is then side two's.
+    ///
+    /// # Errors
+    /// - Neither `time_limit` nor `iterations` given
+    /// - Invalid perspective
+    /// - `config` asks for Dirichlet noise or a node or memory budget, which this search
+    ///   doesn't use
+    #[pyo3(signature = (
+        time_limit=None,
+        iterations=None,
+        config=None,
+        export_depth=None,
+        seed=None,
+        perspective="side_one",
+    ))]
+    fn perform_mcts_search_st(
+        &self,
+        py: Python<'_>,
+        time_limit: Option<u64>,
+        iterations: Option<u32>,
+        config: Option<PyMctsConfig>,
+        export_depth: Option<usize>,
+        seed: Option<u64>,
+        perspective: &str,
+    ) -> PyResult<PySearchResult> {
+        let time_limit = search_time_limit(time_limit, iterations)?;
+        let perspective = search_perspective(perspective)?;
+        let mut config = search_config(config, Search::SingleThreaded)?;
+        config.seed = seed;
+        let mut search_state = perspective.orient(&self.state);
+
+        let mut result = py.allow_threads(|| {
+            perform_mcts_search_st(
+                &mut search_state,
+                iterations,
+                time_limit,
+                &config,
+                export_depth,
+            )
+        });
+        if perspective == Perspective::SideTwo {
+            result.swap_sides();
+        }
+
+        Ok(PySearchResult::from_search_result(&result))
+    }
+
+    /// Runs a PUCT search guided by a policy/value network. `evaluator` is called with a
+    /// list of up to `config.batch_size` observations, each from the searching side's
+    /// view, and must return `(priors, values)`: one list of ACTION_SPACE move priors
+    /// (six move slots, then six switches) and one win probability in [0, 1] per
+    /// observation. Priors are masked to the legal moves and renormalised.
+    ///
+    /// # Errors
+    /// - Neither `time_limit` nor `iterations` given
+    /// - Invalid perspective
+    /// - `evaluator` raises or returns outputs of the wrong shape
+    /// - `evaluator` returns a NaN or infinite value or prior
+    /// - `config` asks for transpositions, the solver or a node or memory budget, which
+    ///   this search doesn't use
+    #[pyo3(signature = (
+        evaluator,
+        time_limit=None,
+        iterations=None,
+        config=None,
+        export_depth=None,
+        seed=None,
+        perspective="side_one",
+    ))]
+    fn perform_puct_search(
+        &self,
+        py: Python<'_>,
+        evaluator: PyObject,
+        time_limit: Option<u64>,
+        iterations: Option<u32>,
+        config: Option<PyMctsConfig>,
+        export_depth: Option<usize>,
+        seed: Option<u64>,
+        perspective: &str,
+    ) -> PyResult<PySearchResult> {
+        let time_limit = search_time_limit(time_limit, iterations)?;
+        let perspective = search_perspective(perspective)?;
+        let mut config = search_config(config, Search::Puct)?;
+        config.seed = seed;
+        let search_state = perspective.orient(&self.state);
+
+        let mut result = py.allow_threads(|| {
+            perform_puct_search(
+                &search_state,
+                iterations,
+                time_limit,
+                &config,
+                export_depth,
+                |states| {
+                    let observations = states.iter().map(|state| {
+                        let [observation, _] = encode_observations(state);
+                        observation
+                    });
+                    Python::with_gil(|py| {
+                        let (priors, values): (Vec<Vec<f32>>, Vec<f32>) = evaluator
+                            .call1(py, (observations.collect::<Vec<_>>(),))?
+                            .extract(py)?;
+                        leaf_evaluations(priors, values, states.len())
+                    })
+                },
+            )
+        })?;
+        if perspective == Perspective::SideTwo {
+            result.swap_sides();
+        }
 
-        perform_mcts_search_st(&mut self.state, None, Some(time_limit))
+        Ok(PySearchResult::from_search_result(&result))
+    }
+
+    /// Returns side one's strategy, side two's strategy, the root value and the
+    /// number of iterations that ran. A `seed` makes the search reproducible for a
+    /// fixed iteration count.
+    ///
+    /// # Errors
+    /// - Neither `time_limit` nor `iterations` given
+    /// - `config` asks for Dirichlet noise, transpositions, the solver or a node or
+    ///   memory budget, which this search doesn't use
+    #[pyo3(signature = (time_limit=None, iterations=None, config=None, seed=None))]
+    fn perform_simultaneous_search(
+        &self,
+        py: Python<'_>,
+        time_limit: Option<u64>,
+        iterations: Option<u32>,
+        config: Option<PyMctsConfig>,
+        seed: Option<u64>,
+    ) -> PyResult<SimultaneousResult> {
+        let time_limit = search_time_limit(time_limit, iterations)?;
+        let mut config = search_config(config, Search::Simultaneous)?;
+        config.seed = seed;
+
+        Ok(py.allow_threads(|| {
+            perform_simultaneous_search(&self.state, iterations, time_limit, &config)
+        }))
     }
 
     fn serialize(&self) -> String {
         self.state.serialize()
     }
 
+    /// Hash of every field of the state, the same for equal states and across runs of
+    /// one build
+    fn hash(&self) -> u64 {
+        state_hash(&self.state)
+    }
+
     fn deserialize(&mut self, serialized: &str) -> () {
         self.state = State::deserialize(serialized);
     }
 }
 
+// Converts a time_limit in milliseconds, requiring at least one search budget
+pub fn search_time_limit(
+    time_limit: Option<u64>,
+    iterations: Option<u32>,
+) -> PyResult<Option<Duration>> {
+    if time_limit.is_none() && iterations.is_none() {
+        return Err(PyValueError::new_err(
+            "Either time_limit or iterations must be given",
+        ));
+    }
+
+    Ok(time_limit.map(Duration::from_millis))
+}
+
+// The searches exposed to Python, each honouring a different subset of the config
+#[derive(Clone, Copy, PartialEq)]
+pub enum Search {
+    // perform_mcts_search, SearchSession and SearchHandle
+    Parallel,
+    SingleThreaded,
+    Simultaneous,
+    Puct,
+}
+
+// Unwraps a search's config, rejecting the options that search would silently ignore
+pub fn search_config(config: Option<PyMctsConfig>, search: Search) -> PyResult<MctsConfig> {
+    let config = config.unwrap_or_default().config;
+    let unsupported = if config.dirichlet_epsilon > 0.0 && search != Search::Puct {
+        Some("Dirichlet noise only applies to the PUCT search")
+    } else if config.transpositions && search != Search::SingleThreaded {
+        Some("Transpositions only apply to perform_mcts_search_st")
+    } else if config.solver && search != Search::SingleThreaded {
+        Some("The solver only applies to perform_mcts_search_st")
+    } else if (config.max_nodes.is_some() || config.max_memory_bytes.is_some())
+        && search != Search::Parallel
+    {
+        Some("Node and memory budgets only apply to perform_mcts_search, SearchSession and SearchHandle")
+    } else {
+        None
+    };
+
+    match unsupported {
+        Some(message) => Err(PyValueError::new_err(message)),
+        None => Ok(config),
+    }
+}
+
+// Pairs up a network's outputs for `leaves` leaves, checking their shapes and that
+// they're finite
+pub fn leaf_evaluations(
+    priors: Vec<Vec<f32>>,
+    values: Vec<f32>,
+    leaves: usize,
+) -> PyResult<Vec<LeafEvaluation>> {
+    if priors.len() != leaves || values.len() != leaves {
+        return Err(PyValueError::new_err(format!(
+            "Expected priors and values for {leaves} leaves, got {} and {}",
+            priors.len(),
+            values.len()
+        )));
+    }
+    if let Some(p) = priors.iter().find(|p| p.len() != ACTION_SPACE) {
+        return Err(PyValueError::new_err(format!(
+            "Expected {ACTION_SPACE} priors per leaf, got {}",
+            p.len()
+        )));
+    }
+    if let Some(v) = values.iter().find(|v| !v.is_finite()) {
+        return Err(PyValueError::new_err(format!("Invalid leaf value: {v}")));
+    }
+    if let Some(p) = priors.iter().flatten().find(|p| !p.is_finite()) {
+        return Err(PyValueError::new_err(format!("Invalid leaf prior: {p}")));
+    }
+
+    Ok(priors
+        .into_iter()
+        .zip(values)
+        .map(|(priors, value)| LeafEvaluation { priors, value })
+        .collect())
+}
+
+pub fn search_perspective(perspective: &str) -> PyResult<Perspective> {
+    match Perspective::from_str(perspective) {
+        Ok(p) => Ok(p),
+        Err(()) => Err(PyValueError::new_err(format!(
+            "Invalid perspective: {perspective}"
+        ))),
+    }
+}
+
+// Pools built for each `num_threads`, kept so repeated searches don't start and join
+// their threads every call
+static THREAD_POOLS: Mutex<BTreeMap<usize, Arc<ThreadPool>>> = Mutex::new(BTreeMap::new());
+
+// The pool with `num_threads` threads, built on first use. None keeps the global
+// rayon pool.
+pub fn search_thread_pool(num_threads: Option<usize>) -> PyResult<Option<Arc<ThreadPool>>> {
+    let Some(num_threads) = num_threads else {
+        return Ok(None);
+    };
+    if num_threads == 0 {
+        return Err(PyValueError::new_err("num_threads must be at least 1"));
+    }
+
+    let mut pools = THREAD_POOLS.lock().unwrap();
+    if let Some(pool) = pools.get(&num_threads) {
+        return Ok(Some(Arc::clone(pool)));
+    }
+    match ThreadPoolBuilder::new().num_threads(num_threads).build() {
+        Ok(pool) => {
+            let pool = Arc::new(pool);
+            pools.insert(num_threads, Arc::clone(&pool));
+            Ok(Some(pool))
+        }
+        Err(e) => Err(PyRuntimeError::new_err(format!(
+            "Failed to build thread pool: {e}"
+        ))),
+    }
+}
+
 #[derive(Clone)]
 #[pyclass(get_all, set_all)]
 struct PyStateInstructions {
@@ -0,0 +1,144 @@
+use poke_engine::{
+    evaluate::evaluate,
+    generate_instructions::generate_instructions_from_move_pair,
+    instruction::StateInstructions,
+    state::{MoveChoice, State},
+};
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::mcts_config::{MctsConfig, RolloutPolicy, RolloutWeights};
+use crate::valid_moves::ValidMoves;
+
+const MAX_DAMAGE_WEIGHTS: RolloutWeights = RolloutWeights {
+    damage: 1.0,
+    evaluation: 0.0,
+    switch: 0.0,
+};
+
+// Plays a copy of `state` forward with the configured policy for both sides, until the
+// battle ends or `rollout_depth` turns have passed. None when rollouts are off.
+pub fn rollout(state: &State, config: &MctsConfig, rng: &mut StdRng) -> Option<State> {
+    let weights = match config.rollout_policy {
+        RolloutPolicy::None => return None,
+        RolloutPolicy::Random => None,
+        RolloutPolicy::MaxDamage => Some(MAX_DAMAGE_WEIGHTS),
+        RolloutPolicy::Heuristic => Some(config.rollout_weights),
+    };
+
+    let mut state = state.clone();
+    for _ in 0..config.rollout_depth {
+        if state.battle_is_over() != 0.0 {
+            break;
+        }
+
+        let moves = ValidMoves::new(&state);
+        if moves.is_terminal() {
+            break;
+        }
+        let side_one_move = choose_move(&mut state, &moves.ours, true, weights, rng);
+        let side_two_move = choose_move(&mut state, &moves.theirs, false, weights, rng);
+
+        let instructions =
+            generate_instructions_from_move_pair(&mut state, &side_one_move, &side_two_move, true);
+        if let Some(chosen) = instructions.get(sample_branch(&instructions, rng)) {
+            state.apply_instructions(&chosen.instruction_list);
+        }
+    }
+
+    Some(state)
+}
+
+// Random without weights, otherwise the best scoring move with ties broken at random
+fn choose_move(
+    state: &mut State,
+    moves: &[MoveChoice],
+    side_one: bool,
+    weights: Option<RolloutWeights>,
+    rng: &mut StdRng,
+) -> MoveChoice {
+    let Some(weights) = weights.filter(|_| moves.len() > 1) else {
+        return moves.choose(rng).copied().unwrap_or(MoveChoice::None);
+    };
+
+    let scores: Vec<f32> = moves
+        .iter()
+        .map(|m| move_score(state, *m, side_one, weights))
+        .collect();
+    let best_score = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let best_moves: Vec<MoveChoice> = moves
+        .iter()
+        .zip(&scores)
+        .filter(|(_, score)| **score >= best_score)
+        .map(|(m, _)| *m)
+        .collect();
+
+    best_moves.choose(rng).copied().unwrap_or(moves[0])
+}
+
+// Expected share of the opponent's active HP a move of ours takes, as a selection prior
+pub fn damage_prior(state: &mut State, mov: MoveChoice) -> f32 {
+    move_score(state, mov, true, MAX_DAMAGE_WEIGHTS).clamp(0.0, 1.0)
+}
+
+// Scores a move against an opponent that does nothing, averaging each feature over
+// the move's instruction branches
+fn move_score(state: &mut State, mov: MoveChoice, side_one: bool, weights: RolloutWeights) -> f32 {
+    let (side_one_move, side_two_move, sign) = if side_one {
+        (mov, MoveChoice::None, 1.0)
+    } else {
+        (MoveChoice::None, mov, -1.0)
+    };
+    let target_hp = |state: &State| {
+        let target = if side_one {
+            state.side_two.get_active_immutable()
+        } else {
+            state.side_one.get_active_immutable()
+        };
+        (target.hp, target.maxhp)
+    };
+
+    // Evaluating the state is the costly part, and max damage doesn't weigh it at all
+    let use_evaluation = weights.evaluation != 0.0;
+    let (hp_before, max_hp) = target_hp(state);
+    let evaluation_before = if use_evaluation { evaluate(state) } else { 0.0 };
+    let mut damage = 0.0;
+    let mut evaluation = 0.0;
+
+    let instructions =
+        generate_instructions_from_move_pair(state, &side_one_move, &side_two_move, false);
+    for branch in &instructions {
+        let chance = branch.percentage / 100.0;
+        state.apply_instructions(&branch.instruction_list);
+
+        let (hp_after, _) = target_hp(state);
+        damage += chance * f32::from(hp_before - hp_after) / f32::from(max_hp.max(1));
+        if use_evaluation {
+            evaluation += chance * sign * (evaluate(state) - evaluation_before);
+        }
+
+        state.reverse_instructions(&branch.instruction_list);
+    }
+
+    let switch = if matches!(mov, MoveChoice::Switch(_)) {
+        1.0
+    } else {
+        0.0
+    };
+    weights.damage * damage + weights.evaluation * evaluation + weights.switch * switch
+}
+
+// Index of a random instruction branch, weighted by chance. The RNG is only drawn
+// from when there's more than one branch.
+pub fn sample_branch(instructions: &[StateInstructions], rng: &mut StdRng) -> usize {
+    if instructions.len() <= 1 {
+        return 0;
+    }
+
+    let weights = instructions.iter().map(|i| f64::from(i.percentage));
+    match WeightedIndex::new(weights) {
+        Ok(dist) => dist.sample(rng),
+        Err(_) => 0,
+    }
+}
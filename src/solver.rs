@@ -0,0 +1,143 @@
+use poke_engine::{
+    generate_instructions::generate_instructions_from_move_pair,
+    state::{MoveChoice, State},
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::state_hash::state_hash;
+use crate::valid_moves::ValidMoves;
+
+// Proven result of a state for side one, with both sides moving simultaneously
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Proof {
+    // this move wins against every reply and every instruction branch
+    Win(MoveChoice),
+    // one opponent move wins against everything we can do
+    Loss,
+}
+
+// Proofs keyed by state hash. They hold for the exact state rather than a tree node,
+// so they stay sound in the open-loop trees where a node covers many states.
+#[derive(Default)]
+pub struct Solver {
+    proofs: HashMap<u64, Proof>,
+    // moves that lose outright to every opponent move, in unproven states
+    lost_moves: HashMap<u64, Vec<MoveChoice>>,
+    // (state, proven state one joint move below it) pairs a proof has been passed
+    // up along. Terminal states are never in `proofs`, so this is tracked apart.
+    propagated: HashSet<(u64, u64)>,
+}
+
+impl Solver {
+    pub fn proof(&self, hash: u64) -> Option<Proof> {
+        self.proofs.get(&hash).copied()
+    }
+
+    pub fn lost_moves(&self, hash: u64) -> &[MoveChoice] {
+        self.lost_moves.get(&hash).map_or(&[], Vec::as_slice)
+    }
+
+    // Rough heap memory of the maps, one control byte per bucket as in the tree's
+    pub fn memory_bytes(&self) -> usize {
+        self.proofs.capacity() * (size_of::<(u64, Proof)>() + 1)
+            + self.lost_moves.capacity() * (size_of::<(u64, Vec<MoveChoice>)>() + 1)
+            + self
+                .lost_moves
+                .values()
+                .map(|moves| moves.capacity() * size_of::<MoveChoice>())
+                .sum::<usize>()
+            + self.propagated.capacity() * (size_of::<(u64, u64)>() + 1)
+    }
+
+    // Whether `parent` hasn't been solved with its proven child `child` yet, noting
+    // that it now will be
+    pub fn first_propagation(&mut self, parent: u64, child: u64) -> bool {
+        self.propagated.insert((parent, child))
+    }
+
+    // Side one's exact result from `state`, if the battle is over or it's been proven
+    pub fn proven_value(&self, state: &State) -> Option<f32> {
+        let result = state.battle_is_over();
+        if result != 0.0 {
+            return Some(if result > 0.0 { 1.0 } else { 0.0 });
+        }
+
+        match self.proof(state_hash(state))? {
+            Proof::Win(_) => Some(1.0),
+            Proof::Loss => Some(0.0),
+        }
+    }
+
+    // Tries to prove `state` from the states one joint move away, playing every
+    // instruction branch of every pair of moves. `state` is left as it was.
+    pub fn solve(&mut self, state: &mut State) -> Option<f32> {
+        if let Some(value) = self.proven_value(state) {
+            return Some(value);
+        }
+
+        let moves = ValidMoves::new(state);
+        if moves.is_terminal() {
+            return None;
+        }
+        let (our_moves, opp_moves) = (moves.ours, moves.theirs);
+
+        // exact result of each joint move, None unless every branch agrees
+        let mut results = vec![vec![None; opp_moves.len()]; our_moves.len()];
+        for (our_move, row) in our_moves.iter().zip(&mut results) {
+            for (opp_move, result) in opp_moves.iter().zip(row.iter_mut()) {
+                *result = self.joint_move_value(state, our_move, opp_move);
+            }
+        }
+
+        let hash = state_hash(state);
+        let winning_move = our_moves
+            .iter()
+            .zip(&results)
+            .find(|(_, row)| row.iter().all(|r| *r == Some(1.0)))
+            .map(|(m, _)| *m);
+        let losing = (0..opp_moves.len()).any(|j| results.iter().all(|row| row[j] == Some(0.0)));
+
+        if let Some(m) = winning_move {
+            self.proofs.insert(hash, Proof::Win(m));
+            Some(1.0)
+        } else if losing {
+            self.proofs.insert(hash, Proof::Loss);
+            Some(0.0)
+        } else {
+            let lost: Vec<MoveChoice> = our_moves
+                .iter()
+                .zip(&results)
+                .filter(|(_, row)| row.iter().all(|r| *r == Some(0.0)))
+                .map(|(m, _)| *m)
+                .collect();
+            if !lost.is_empty() {
+                self.lost_moves.insert(hash, lost);
+            }
+            None
+        }
+    }
+
+    fn joint_move_value(
+        &self,
+        state: &mut State,
+        our_move: &MoveChoice,
+        opp_move: &MoveChoice,
+    ) -> Option<f32> {
+        let instructions = generate_instructions_from_move_pair(state, our_move, opp_move, true);
+        let mut value = None;
+
+        for branch in &instructions {
+            state.apply_instructions(&branch.instruction_list);
+            let branch_value = self.proven_value(state);
+            state.reverse_instructions(&branch.instruction_list);
+
+            match (value, branch_value) {
+                (_, None) => return None,
+                (Some(v), Some(b)) if v != b => return None,
+                (_, b) => value = b,
+            }
+        }
+
+        value
+    }
+}
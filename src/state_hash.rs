@@ -0,0 +1,197 @@
+use poke_engine::state::{LastUsedMove, Move, Pokemon, Side, State};
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// FNV-1a. Unlike the std hashers it's seeded the same way every run, so a state
+// hashes the same across runs of one build.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(FNV_OFFSET)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+// Hashes every field of the state without serializing it. Enums are hashed by
+// variant, and volatile statuses by a sum that doesn't depend on the set's order.
+pub fn state_hash(state: &State) -> u64 {
+    let mut hasher = Fnv::default();
+    hash_side(&state.side_one, &mut hasher);
+    hash_side(&state.side_two, &mut hasher);
+    discriminant(&state.weather.weather_type).hash(&mut hasher);
+    state.weather.turns_remaining.hash(&mut hasher);
+    discriminant(&state.terrain.terrain_type).hash(&mut hasher);
+    state.terrain.turns_remaining.hash(&mut hasher);
+    state.trick_room.active.hash(&mut hasher);
+    state.trick_room.turns_remaining.hash(&mut hasher);
+    state.team_preview.hash(&mut hasher);
+    state.use_damage_dealt.hash(&mut hasher);
+    state.use_last_used_move.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn hash_side(side: &Side, hasher: &mut Fnv) {
+    discriminant(&side.active_index).hash(hasher);
+    side.baton_passing.hash(hasher);
+    let pokemon = &side.pokemon;
+    for p in [
+        &pokemon.p0,
+        &pokemon.p1,
+        &pokemon.p2,
+        &pokemon.p3,
+        &pokemon.p4,
+        &pokemon.p5,
+    ] {
+        hash_pokemon(p, hasher);
+    }
+
+    let c = &side.side_conditions;
+    [
+        c.spikes,
+        c.toxic_spikes,
+        c.stealth_rock,
+        c.sticky_web,
+        c.tailwind,
+        c.lucky_chant,
+        c.lunar_dance,
+        c.reflect,
+        c.light_screen,
+        c.aurora_veil,
+        c.crafty_shield,
+        c.safeguard,
+        c.mist,
+        c.protect,
+        c.healing_wish,
+        c.mat_block,
+        c.quick_guard,
+        c.toxic_count,
+        c.wide_guard,
+    ]
+    .hash(hasher);
+
+    side.wish.hash(hasher);
+    side.future_sight.0.hash(hasher);
+    discriminant(&side.future_sight.1).hash(hasher);
+    side.force_switch.hash(hasher);
+    side.force_trapped.hash(hasher);
+    side.slow_uturn_move.hash(hasher);
+
+    let volatile_statuses = side
+        .volatile_statuses
+        .iter()
+        .map(|status| {
+            let mut status_hasher = Fnv::default();
+            discriminant(status).hash(&mut status_hasher);
+            status_hasher.finish()
+        })
+        .fold(0, u64::wrapping_add);
+    volatile_statuses.hash(hasher);
+    side.volatile_statuses.len().hash(hasher);
+
+    side.substitute_health.hash(hasher);
+    [
+        side.attack_boost,
+        side.defense_boost,
+        side.special_attack_boost,
+        side.special_defense_boost,
+        side.speed_boost,
+        side.accuracy_boost,
+        side.evasion_boost,
+    ]
+    .hash(hasher);
+
+    match &side.last_used_move {
+        LastUsedMove::Move(index) => (0u8, discriminant(index)).hash(hasher),
+        LastUsedMove::Switch(index) => (1u8, discriminant(index)).hash(hasher),
+        LastUsedMove::None => 2u8.hash(hasher),
+    }
+    side.damage_dealt.damage.hash(hasher);
+    discriminant(&side.damage_dealt.move_category).hash(hasher);
+    side.damage_dealt.hit_substitute.hash(hasher);
+    discriminant(&side.switch_out_move_second_saved_move).hash(hasher);
+}
+
+fn hash_pokemon(pokemon: &Pokemon, hasher: &mut Fnv) {
+    discriminant(&pokemon.id).hash(hasher);
+    pokemon.level.hash(hasher);
+    discriminant(&pokemon.types.0).hash(hasher);
+    discriminant(&pokemon.types.1).hash(hasher);
+    [
+        pokemon.hp,
+        pokemon.maxhp,
+        pokemon.attack,
+        pokemon.defense,
+        pokemon.special_attack,
+        pokemon.special_defense,
+        pokemon.speed,
+    ]
+    .hash(hasher);
+    discriminant(&pokemon.ability).hash(hasher);
+    discriminant(&pokemon.item).hash(hasher);
+    discriminant(&pokemon.status).hash(hasher);
+    pokemon.rest_turns.hash(hasher);
+    pokemon.sleep_turns.hash(hasher);
+    pokemon.weight_kg.to_bits().hash(hasher);
+    pokemon.terastallized.hash(hasher);
+    discriminant(&pokemon.tera_type).hash(hasher);
+
+    let moves = &pokemon.moves;
+    for m in [
+        &moves.m0, &moves.m1, &moves.m2, &moves.m3, &moves.m4, &moves.m5,
+    ] {
+        hash_move(m, hasher);
+    }
+}
+
+fn hash_move(m: &Move, hasher: &mut Fnv) {
+    discriminant(&m.id).hash(hasher);
+    m.disabled.hash(hasher);
+    m.pp.hash(hasher);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::state_hash;
+    use poke_engine::state::{PokemonVolatileStatus, State};
+
+    const STATUSES: [PokemonVolatileStatus; 4] = [
+        PokemonVolatileStatus::CONFUSION,
+        PokemonVolatileStatus::LEECHSEED,
+        PokemonVolatileStatus::SUBSTITUTE,
+        PokemonVolatileStatus::TAUNT,
+    ];
+
+    #[test]
+    fn volatile_status_order_does_not_change_the_hash() {
+        let mut forward = State::default();
+        let mut backward = State::default();
+        for status in STATUSES {
+            forward.side_one.volatile_statuses.insert(status);
+        }
+        for status in STATUSES.into_iter().rev() {
+            backward.side_one.volatile_statuses.insert(status);
+        }
+        assert_eq!(state_hash(&forward), state_hash(&backward));
+
+        backward
+            .side_one
+            .volatile_statuses
+            .remove(&PokemonVolatileStatus::TAUNT);
+        assert_ne!(state_hash(&forward), state_hash(&backward));
+    }
+}
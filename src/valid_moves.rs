@@ -0,0 +1,91 @@
+use poke_engine::state::{MoveChoice, State};
+
+use crate::mcts_config::MctsConfig;
+use crate::rollout::damage_prior;
+
+// The moves selection picks from for each side, both empty once the battle is over
+#[derive(Default)]
+pub struct ValidMoves {
+    pub ours: Vec<MoveChoice>,
+    pub theirs: Vec<MoveChoice>,
+    // damage prior of each of our moves, only at the root with progressive bias
+    priors: Vec<f32>,
+}
+
+impl ValidMoves {
+    // The engine's options are filtered in place, so this allocates nothing beyond
+    // what get_all_options does
+    pub fn new(state: &State) -> Self {
+        let (mut ours, mut theirs) = state.get_all_options();
+        if state.battle_is_over() != 0.0 || (ours.is_empty() && theirs.is_empty()) {
+            return ValidMoves::default();
+        }
+
+        retain_valid_opp_moves(&ours, &mut theirs);
+        retain_valid_our_moves(&mut ours);
+        ValidMoves {
+            ours,
+            theirs,
+            priors: Vec::new(),
+        }
+    }
+
+    // The root's moves, generated once per search. Only here are the priors worth
+    // their cost, so with progressive bias our moves are sorted by prior, most
+    // damaging first.
+    pub fn root(state: &mut State, config: &MctsConfig) -> Self {
+        let mut moves = ValidMoves::new(state);
+        if config.progressive_bias > 0.0 && moves.ours.len() > 1 {
+            let mut scored: Vec<(MoveChoice, f32)> = moves
+                .ours
+                .iter()
+                .map(|m| (*m, damage_prior(state, *m)))
+                .collect();
+            scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+            (moves.ours, moves.priors) = scored.into_iter().unzip();
+        }
+        moves
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.ours.is_empty()
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        size_of::<ValidMoves>()
+            + (self.ours.capacity() + self.theirs.capacity()) * size_of::<MoveChoice>()
+            + self.priors.capacity() * size_of::<f32>()
+    }
+
+    // Progressive bias for our move with `visits` visits, fading as it's searched
+    pub fn bias(&self, our_move: &MoveChoice, visits: i64, config: &MctsConfig) -> f32 {
+        self.ours
+            .iter()
+            .position(|m| m == our_move)
+            .and_then(|i| self.priors.get(i))
+            .map_or(0.0, |prior| {
+                config.progressive_bias * prior / (1 + visits) as f32
+            })
+    }
+}
+
+// Only None when we have to pass, otherwise everything but None
+fn retain_valid_our_moves(our_moves: &mut Vec<MoveChoice>) {
+    if our_moves.contains(&MoveChoice::None) {
+        our_moves.clear();
+        our_moves.push(MoveChoice::None);
+    }
+}
+
+// The opponent only switches, or passes, while they're forced to or we are switching
+fn retain_valid_opp_moves(our_moves: &[MoveChoice], opp_moves: &mut Vec<MoveChoice>) {
+    if opp_moves.contains(&MoveChoice::None) {
+        opp_moves.retain(|m| matches!(m, MoveChoice::Switch(_)));
+        if opp_moves.is_empty() {
+            opp_moves.push(MoveChoice::None);
+        }
+    } else if our_moves.iter().all(|m| matches!(m, MoveChoice::Switch(_))) {
+        opp_moves.clear();
+        opp_moves.push(MoveChoice::None);
+    }
+}